@@ -15,6 +15,7 @@ pub fn main() {
         completed,
         not_processed,
         errored,
+        ..
     } = Watcher::new("long_creation_file_*.txt", |path| {
         std::fs::read_to_string(path).map(|s| s.len())
     })
@@ -39,7 +40,7 @@ fn create_file(number: u8, length: usize) {
     let mut f = std::fs::File::create(&filename).expect("Couldn't create file");
 
     for _ in 0..length {
-        f.write(b".").ok();
+        f.write_all(b".").ok();
         thread::sleep(Duration::from_secs(1));
     }
 }
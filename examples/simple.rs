@@ -10,6 +10,7 @@ pub fn main() {
         completed,
         not_processed,
         errored,
+        ..
     } = Watcher::new("simple_*.txt", |path| {
         std::fs::read_to_string(path).map(|s| s.len())
     })
@@ -34,7 +35,7 @@ fn create_files() {
         let mut f = std::fs::File::create(&filename).expect("Couldn't create file");
 
         for _ in 0..length {
-            f.write(b".").ok();
+            f.write_all(b".").ok();
         }
     }
 
@@ -0,0 +1,975 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError, TryLockError},
+    time::{Duration, SystemTime},
+};
+
+#[cfg(feature = "regex")]
+use std::collections::HashMap;
+
+use crate::throttle::{RateLimiter, Throttled};
+use crate::SkipReason;
+
+/// Context about a matured file that is about to be processed
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct FileEvent {
+    /// The path passed to the callback
+    pub path: PathBuf,
+
+    /// The number of the check during which the file was processed, counting from 1
+    pub tick: u64,
+
+    /// The file's modification time when it was processed
+    pub modified: SystemTime,
+
+    /// The file's size in bytes when it was processed
+    pub size: u64,
+}
+
+/// Processes a file once it has matured.
+///
+/// This is implemented for any `FnMut(&Path) -> Result<T, E>` closure, which is what
+/// [Watcher::new](crate::Watcher::new) accepts. The other implementations in this module adapt
+/// closures with different signatures, such as [Reader].
+pub trait Callback {
+    /// The value produced for a successfully processed file
+    type Output;
+
+    /// The error produced for a file that couldn't be processed
+    type Error;
+
+    fn call(&mut self, path: &Path) -> Result<Self::Output, Self::Error>;
+
+    /// Processes a matured file given context about it. This is what the watcher calls; it
+    /// defaults to calling [call](Self::call) with the file's path.
+    fn call_event(&mut self, event: &FileEvent) -> Result<Self::Output, Self::Error> {
+        self.call(&event.path)
+    }
+
+    /// Other files that were consumed along with `path`, which are deleted along with it when
+    /// [delete_on_completion](crate::Watcher::delete_on_completion) is set.
+    fn companions(&self, _path: &Path) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// The number of logical records (eg, rows or messages) that `output` represents, which
+    /// counts toward [StopCondition::RecordsFound](crate::StopCondition::RecordsFound). Defaults to
+    /// one record per file.
+    fn records(&self, _output: &Self::Output) -> u64 {
+        1
+    }
+
+    /// Whether a file that failed with `error` may be attempted again, up to
+    /// [max_attempts](crate::Watcher::max_attempts). Defaults to retrying every error.
+    fn should_retry(&self, _error: &Self::Error) -> bool {
+        true
+    }
+
+    /// Notified once a file's outcome is final: after it was processed successfully, or after it
+    /// failed and won't be retried. Does nothing by default.
+    fn finished(&self, _path: &Path, _result: Result<&Self::Output, &Self::Error>) {}
+
+    /// Asked after a call fails, whether the file should be recorded in
+    /// [FileResults::skipped](crate::FileResults::skipped) for this reason rather than as an
+    /// error. Defaults to `None`, treating every failure as an error.
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        None
+    }
+}
+
+/// Receives each file's result as soon as it's known, such as to write it to a database or
+/// metrics system while the watcher is still running.
+///
+/// Registered with [Watcher::result_sink](crate::Watcher::result_sink).
+pub trait ResultSink<T, E> {
+    /// Called after a file was processed successfully
+    fn on_success(&self, path: &Path, output: &T);
+
+    /// Called after a file failed and won't be retried
+    fn on_error(&self, path: &Path, error: &E);
+}
+
+impl<F, T, E> Callback for F
+where
+    F: FnMut(&Path) -> Result<T, E>,
+{
+    type Output = T;
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        self(path)
+    }
+}
+
+/// What the watcher needs from its callback besides running it: the types it produces and the
+/// hooks that decide how its results are recorded. Implemented for every [Callback], and for
+/// [Async], whose futures are run by the watcher itself. It's only `pub` so that it may bound the
+/// watcher's methods, and isn't exported.
+pub trait Handler {
+    type Output;
+    type Error;
+
+    /// Runs the callback on a matured file. Only called by the blocking watch methods.
+    fn call_event(&mut self, event: &FileEvent) -> Result<Self::Output, Self::Error>;
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf>;
+
+    fn records(&self, output: &Self::Output) -> u64;
+
+    fn should_retry(&self, error: &Self::Error) -> bool;
+
+    fn finished(&self, path: &Path, result: Result<&Self::Output, &Self::Error>);
+
+    fn skip_reason(&mut self) -> Option<SkipReason>;
+}
+
+impl<F: Callback> Handler for F {
+    type Output = F::Output;
+    type Error = F::Error;
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<F::Output, F::Error> {
+        Callback::call_event(self, event)
+    }
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf> {
+        Callback::companions(self, path)
+    }
+
+    fn records(&self, output: &F::Output) -> u64 {
+        Callback::records(self, output)
+    }
+
+    fn should_retry(&self, error: &F::Error) -> bool {
+        Callback::should_retry(self, error)
+    }
+
+    fn finished(&self, path: &Path, result: Result<&F::Output, &F::Error>) {
+        Callback::finished(self, path, result)
+    }
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        Callback::skip_reason(self)
+    }
+}
+
+/// Invokes a hook whenever the wrapped callback fails, before the error is recorded. Failures
+/// that are recorded as skips instead, such as a file that [WithFile] couldn't open, don't invoke
+/// the hook.
+///
+/// Created by [Watcher::on_error](crate::Watcher::on_error).
+pub struct OnError<F, H> {
+    pub(crate) inner: F,
+    pub(crate) hook: H,
+
+    /// Why the wrapped callback's most recent failure is to be skipped, if it is
+    pub(crate) skipped: Option<SkipReason>,
+}
+
+impl<F: Callback, H: Fn(&Path, &F::Error)> OnError<F, H> {
+    /// Invokes the hook if `result` is an error that will be recorded as such
+    fn report(&mut self, path: &Path, result: &Result<F::Output, F::Error>) {
+        if let Err(e) = result {
+            self.skipped = self.inner.skip_reason();
+            if self.skipped.is_none() {
+                (self.hook)(path, e);
+            }
+        }
+    }
+}
+
+impl<F, H> Callback for OnError<F, H>
+where
+    F: Callback,
+    H: Fn(&Path, &F::Error),
+{
+    type Output = F::Output;
+    type Error = F::Error;
+
+    fn call(&mut self, path: &Path) -> Result<F::Output, F::Error> {
+        let result = self.inner.call(path);
+        self.report(path, &result);
+        result
+    }
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<F::Output, F::Error> {
+        let result = self.inner.call_event(event);
+        self.report(&event.path, &result);
+        result
+    }
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf> {
+        self.inner.companions(path)
+    }
+
+    fn records(&self, output: &F::Output) -> u64 {
+        self.inner.records(output)
+    }
+
+    fn should_retry(&self, error: &F::Error) -> bool {
+        self.inner.should_retry(error)
+    }
+
+    fn finished(&self, path: &Path, result: Result<&F::Output, &F::Error>) {
+        self.inner.finished(path, result)
+    }
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        self.skipped.take()
+    }
+}
+
+/// Only retries errors for which a predicate returns `true`.
+///
+/// Created by [Watcher::retry_if](crate::Watcher::retry_if).
+pub struct RetryIf<F, P> {
+    pub(crate) inner: F,
+    pub(crate) predicate: P,
+}
+
+impl<F, P> Callback for RetryIf<F, P>
+where
+    F: Callback,
+    P: Fn(&F::Error) -> bool,
+{
+    type Output = F::Output;
+    type Error = F::Error;
+
+    fn call(&mut self, path: &Path) -> Result<F::Output, F::Error> {
+        self.inner.call(path)
+    }
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<F::Output, F::Error> {
+        self.inner.call_event(event)
+    }
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf> {
+        self.inner.companions(path)
+    }
+
+    fn records(&self, output: &F::Output) -> u64 {
+        self.inner.records(output)
+    }
+
+    fn should_retry(&self, error: &F::Error) -> bool {
+        (self.predicate)(error) && self.inner.should_retry(error)
+    }
+
+    fn finished(&self, path: &Path, result: Result<&F::Output, &F::Error>) {
+        self.inner.finished(path, result)
+    }
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        self.inner.skip_reason()
+    }
+}
+
+/// Passes each file's final result to a [ResultSink].
+///
+/// Created by [Watcher::result_sink](crate::Watcher::result_sink).
+pub struct WithSink<F: Callback> {
+    pub(crate) inner: F,
+    pub(crate) sink: Box<dyn ResultSink<F::Output, F::Error> + Send + Sync>,
+}
+
+impl<F: Callback> Callback for WithSink<F> {
+    type Output = F::Output;
+    type Error = F::Error;
+
+    fn call(&mut self, path: &Path) -> Result<F::Output, F::Error> {
+        self.inner.call(path)
+    }
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<F::Output, F::Error> {
+        self.inner.call_event(event)
+    }
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf> {
+        self.inner.companions(path)
+    }
+
+    fn records(&self, output: &F::Output) -> u64 {
+        self.inner.records(output)
+    }
+
+    fn should_retry(&self, error: &F::Error) -> bool {
+        self.inner.should_retry(error)
+    }
+
+    fn finished(&self, path: &Path, result: Result<&F::Output, &F::Error>) {
+        self.inner.finished(path, result);
+        match result {
+            Ok(output) => self.sink.on_success(path, output),
+            Err(error) => self.sink.on_error(path, error),
+        }
+    }
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        self.inner.skip_reason()
+    }
+}
+
+/// Drops the wrapped callback's output as soon as it returns, if enabled, keeping only whether it
+/// succeeded. Outputs are `Some` while they're kept and `None` once they're dropped.
+///
+/// Created by [Watcher::discard_results](crate::Watcher::discard_results).
+pub struct Discard<F> {
+    pub(crate) inner: F,
+
+    /// Whether outputs are dropped rather than kept
+    pub(crate) discard: bool,
+
+    /// The number of records represented by the most recently discarded output
+    pub(crate) records: u64,
+}
+
+impl<F: Callback> Discard<F> {
+    /// Counts and reports `output` before it's dropped, since only `None` is left afterward
+    fn discard(&mut self, path: &Path, output: F::Output) -> Option<F::Output> {
+        if !self.discard {
+            return Some(output);
+        }
+
+        self.records = self.inner.records(&output);
+        self.inner.finished(path, Ok(&output));
+        None
+    }
+}
+
+impl<F: Callback> Callback for Discard<F> {
+    type Output = Option<F::Output>;
+    type Error = F::Error;
+
+    fn call(&mut self, path: &Path) -> Result<Option<F::Output>, F::Error> {
+        let output = self.inner.call(path)?;
+        Ok(self.discard(path, output))
+    }
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<Option<F::Output>, F::Error> {
+        let output = self.inner.call_event(event)?;
+        Ok(self.discard(&event.path, output))
+    }
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf> {
+        self.inner.companions(path)
+    }
+
+    fn records(&self, output: &Option<F::Output>) -> u64 {
+        match output {
+            Some(output) => self.inner.records(output),
+            None => self.records,
+        }
+    }
+
+    fn should_retry(&self, error: &F::Error) -> bool {
+        self.inner.should_retry(error)
+    }
+
+    fn finished(&self, path: &Path, result: Result<&Option<F::Output>, &F::Error>) {
+        match result {
+            Ok(Some(output)) => self.inner.finished(path, Ok(output)),
+            // Already reported along with the output, before it was dropped
+            Ok(None) => {}
+            Err(error) => self.inner.finished(path, Err(error)),
+        }
+    }
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        self.inner.skip_reason()
+    }
+}
+
+/// Runs the wrapped callback on a helper thread, abandoning calls that take longer than a limit.
+///
+/// Created by [Watcher::per_file_timeout](crate::Watcher::per_file_timeout).
+pub struct Timeout<F> {
+    pub(crate) inner: Arc<Mutex<F>>,
+    pub(crate) limit: Duration,
+}
+
+impl<F: Callback> Timeout<F> {
+    /// The wrapped callback, or `None` if an abandoned call is still using it
+    fn inner(&self) -> Option<MutexGuard<'_, F>> {
+        match self.inner.try_lock() {
+            Ok(inner) => Some(inner),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
+
+impl<F> Timeout<F>
+where
+    F: Callback + Send + 'static,
+    F::Output: Send + 'static,
+    F::Error: From<std::io::Error> + Send + 'static,
+{
+    /// Calls the wrapped callback on a new thread, waiting up to the limit for its result
+    fn run<C>(&self, call: C) -> Result<F::Output, F::Error>
+    where
+        C: FnOnce(&mut F) -> Result<F::Output, F::Error> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        let (sender, receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            // The lock is released before sending, so that a finished call never holds it
+            let result = call(&mut inner.lock().unwrap_or_else(PoisonError::into_inner));
+            let _ = sender.send(result);
+        });
+
+        match receiver.recv_timeout(self.limit) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("callback didn't finish within {:?}", self.limit),
+            )
+            .into()),
+            Err(mpsc::RecvTimeoutError::Disconnected) => match handle.join() {
+                Err(payload) => std::panic::resume_unwind(payload),
+                Ok(()) => unreachable!("the callback's thread finished without a result"),
+            },
+        }
+    }
+}
+
+impl<F> Callback for Timeout<F>
+where
+    F: Callback + Send + 'static,
+    F::Output: Send + 'static,
+    F::Error: From<std::io::Error> + Send + 'static,
+{
+    type Output = F::Output;
+    type Error = F::Error;
+
+    fn call(&mut self, path: &Path) -> Result<F::Output, F::Error> {
+        let path = path.to_path_buf();
+        self.run(move |inner| inner.call(&path))
+    }
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<F::Output, F::Error> {
+        let event = event.clone();
+        self.run(move |inner| inner.call_event(&event))
+    }
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf> {
+        self.inner()
+            .map_or_else(Vec::new, |inner| inner.companions(path))
+    }
+
+    fn records(&self, output: &F::Output) -> u64 {
+        self.inner().map_or(1, |inner| inner.records(output))
+    }
+
+    fn should_retry(&self, error: &F::Error) -> bool {
+        self.inner().is_none_or(|inner| inner.should_retry(error))
+    }
+
+    fn finished(&self, path: &Path, result: Result<&F::Output, &F::Error>) {
+        if let Some(inner) = self.inner() {
+            inner.finished(path, result);
+        }
+    }
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        self.inner().and_then(|mut inner| inner.skip_reason())
+    }
+}
+
+/// Wraps a closure that returns the number of logical records it produced along with its result.
+///
+/// Created by [Watcher::new_counted](crate::Watcher::new_counted).
+pub struct Counted<G>(pub(crate) G);
+
+impl<G, T, E> Callback for Counted<G>
+where
+    G: FnMut(&Path) -> Result<(T, u64), E>,
+{
+    type Output = (T, u64);
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<(T, u64), E> {
+        (self.0)(path)
+    }
+
+    fn records(&self, output: &(T, u64)) -> u64 {
+        output.1
+    }
+}
+
+/// Passes each matured file to the wrapped closure along with the number of the check during
+/// which it was processed, for correlating log output.
+///
+/// Created by [Watcher::new_ticked](crate::Watcher::new_ticked).
+pub struct Ticked<G>(pub(crate) G);
+
+impl<G, T, E> Callback for Ticked<G>
+where
+    G: FnMut(&Path, u64) -> Result<T, E>,
+{
+    type Output = T;
+    type Error = E;
+
+    /// Called without any context, the tick is reported as 0
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        (self.0)(path, 0)
+    }
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<T, E> {
+        (self.0)(&event.path, event.tick)
+    }
+}
+
+/// Passes each matured file's [FileEvent], including its size and modification time, to the
+/// wrapped closure.
+///
+/// Created by [Watcher::with_metadata_callback](crate::Watcher::with_metadata_callback).
+pub struct WithMetadata<G>(pub(crate) G);
+
+impl<G, T, E> Callback for WithMetadata<G>
+where
+    G: FnMut(&FileEvent) -> Result<T, E>,
+    E: From<std::io::Error>,
+{
+    type Output = T;
+    type Error = E;
+
+    /// Called without any context, the file's metadata is read and the tick is reported as 0
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        let metadata = path.metadata()?;
+        let event = FileEvent {
+            path: path.to_path_buf(),
+            tick: 0,
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        };
+        (self.0)(&event)
+    }
+
+    fn call_event(&mut self, event: &FileEvent) -> Result<T, E> {
+        (self.0)(event)
+    }
+}
+
+/// Opens each matured file for reading and passes the handle to the wrapped closure along with
+/// the file's path.
+///
+/// Created by [Watcher::with_file_callback](crate::Watcher::with_file_callback).
+pub struct WithFile<G> {
+    pub(crate) callback: G,
+
+    /// Why the most recently matured file couldn't be opened, if it couldn't
+    pub(crate) open_error: Option<std::io::Error>,
+}
+
+impl<G, T, E> Callback for WithFile<G>
+where
+    G: FnMut(PathBuf, File) -> Result<T, E>,
+    E: From<std::io::Error>,
+{
+    type Output = T;
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        match File::open(path) {
+            Ok(file) => {
+                self.open_error = None;
+                (self.callback)(path.to_path_buf(), file)
+            }
+            Err(e) => {
+                let error = std::io::Error::new(e.kind(), e.to_string());
+                self.open_error = Some(e);
+                Err(error.into())
+            }
+        }
+    }
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        self.open_error
+            .take()
+            .map(|error| SkipReason::OpenFailed { error })
+    }
+}
+
+/// A callback that does nothing, for watchers that only report which files have matured.
+///
+/// Created by [Watcher::detect](crate::Watcher::detect).
+pub struct Detect;
+
+impl Callback for Detect {
+    type Output = ();
+    type Error = std::io::Error;
+
+    fn call(&mut self, _path: &Path) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+/// Opens each matured file and passes a reader over its contents to the wrapped closure.
+///
+/// Created by [Watcher::new_reader](crate::Watcher::new_reader).
+pub struct Reader<G> {
+    callback: G,
+
+    /// Whether `.gz` files should be decompressed before being passed to the callback
+    #[cfg(feature = "flate2")]
+    pub(crate) decompress: bool,
+
+    /// If set, files ending with this suffix are manifests listing parts to be concatenated
+    pub(crate) manifest_suffix: Option<String>,
+
+    /// If set, paces how quickly files are read
+    pub(crate) rate_limit: Option<RateLimiter>,
+}
+
+impl<G> Reader<G> {
+    pub(crate) fn new(callback: G) -> Self {
+        Reader {
+            callback,
+            #[cfg(feature = "flate2")]
+            decompress: false,
+            manifest_suffix: None,
+            rate_limit: None,
+        }
+    }
+
+    /// If `path` is a manifest, returns the path of the logical file it describes along with
+    /// the parts that make it up, in order. All parts must exist.
+    fn parts(&self, path: &Path) -> Result<Option<(PathBuf, Vec<PathBuf>)>, std::io::Error> {
+        let Some(suffix) = &self.manifest_suffix else {
+            return Ok(None);
+        };
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let Some(logical) = name.strip_suffix(suffix.as_str()) else {
+            return Ok(None);
+        };
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let parts: Vec<_> = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| dir.join(line))
+            .collect();
+
+        if let Some(missing) = parts.iter().find(|part| !part.exists()) {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "part {} listed in {} is missing",
+                    missing.display(),
+                    path.display()
+                ),
+            ));
+        }
+
+        Ok(Some((dir.join(logical), parts)))
+    }
+}
+
+impl<G, T, E> Callback for Reader<G>
+where
+    G: FnMut(&Path, &mut dyn Read) -> Result<T, E>,
+    E: From<std::io::Error>,
+{
+    type Output = T;
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        let (path, reader): (PathBuf, Box<dyn BufRead>) = match self.parts(path)? {
+            Some((logical, parts)) => {
+                let mut reader: Box<dyn BufRead> = Box::new(std::io::empty());
+                for part in parts {
+                    reader = Box::new(reader.chain(BufReader::new(File::open(part)?)));
+                }
+                (logical, reader)
+            }
+            None => (
+                path.to_path_buf(),
+                Box::new(BufReader::new(File::open(path)?)),
+            ),
+        };
+
+        let mut reader: Box<dyn BufRead + '_> = match &mut self.rate_limit {
+            Some(limiter) => Box::new(BufReader::new(Throttled {
+                inner: reader,
+                limiter,
+            })),
+            None => reader,
+        };
+
+        #[cfg(feature = "flate2")]
+        if self.decompress && path.extension().is_some_and(|ext| ext == "gz") {
+            let mut decoder = flate2::bufread::MultiGzDecoder::new(reader);
+            return (self.callback)(&path, &mut decoder);
+        }
+
+        (self.callback)(&path, &mut reader)
+    }
+
+    fn companions(&self, path: &Path) -> Vec<PathBuf> {
+        match self.parts(path) {
+            Ok(Some((_, parts))) => parts,
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Reads at most the first few bytes of each matured file and passes them to the wrapped closure,
+/// for callbacks that only need a file's header.
+///
+/// Created by [Watcher::new_header](crate::Watcher::new_header).
+pub struct Header<G> {
+    pub(crate) callback: G,
+
+    /// The maximum number of bytes to read
+    pub(crate) len: usize,
+
+    /// If set, paces how quickly files are read
+    pub(crate) rate_limit: Option<RateLimiter>,
+}
+
+impl<G, T, E> Callback for Header<G>
+where
+    G: FnMut(&Path, &[u8]) -> Result<T, E>,
+    E: From<std::io::Error>,
+{
+    type Output = T;
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        let file = File::open(path)?;
+        // `len` may be far larger than the file, such as `usize::MAX` to read all of it
+        let size = file.metadata().map_or(0, |metadata| metadata.len());
+        let mut header = Vec::with_capacity(size.min(self.len as u64) as usize);
+        let mut file = file.take(self.len as u64);
+        match &mut self.rate_limit {
+            Some(limiter) => Throttled {
+                inner: file,
+                limiter,
+            }
+            .read_to_end(&mut header)?,
+            None => file.read_to_end(&mut header)?,
+        };
+
+        (self.callback)(path, &header)
+    }
+}
+
+/// Passes each matured file to the wrapped closure along with a writer for a single output that
+/// is shared across all files, such as an aggregate file that every result is appended to.
+///
+/// Created by [Watcher::new_writer](crate::Watcher::new_writer).
+pub struct Aggregate<G> {
+    callback: G,
+
+    /// The file that output is appended to, or stdout if unset
+    pub(crate) output: Option<PathBuf>,
+
+    /// The opened output, created on first use
+    file: Option<File>,
+}
+
+impl<G> Aggregate<G> {
+    pub(crate) fn new(callback: G) -> Self {
+        Aggregate {
+            callback,
+            output: None,
+            file: None,
+        }
+    }
+}
+
+impl<G, T, E> Callback for Aggregate<G>
+where
+    G: FnMut(&Path, &mut dyn Write) -> Result<T, E>,
+    E: From<std::io::Error>,
+{
+    type Output = T;
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        let Some(output) = &self.output else {
+            let mut stdout = std::io::stdout().lock();
+            let result = (self.callback)(path, &mut stdout);
+            stdout.flush()?;
+            return result;
+        };
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self
+                .file
+                .insert(OpenOptions::new().create(true).append(true).open(output)?),
+        };
+
+        // Each file's output is buffered and flushed before the next file is processed.
+        let mut writer = BufWriter::new(file);
+        let result = (self.callback)(path, &mut writer);
+        writer.flush()?;
+        result
+    }
+}
+
+/// Memory-maps each matured file and passes its contents to the wrapped closure, avoiding
+/// reading large files onto the heap.
+///
+/// Created by [Watcher::new_mmap](crate::Watcher::new_mmap).
+#[cfg(feature = "memmap2")]
+pub struct Mapped<G> {
+    pub(crate) callback: G,
+
+    /// If set, paces how quickly files are consumed. Since pages of the map are read lazily, each
+    /// file's full size is counted against the limit before the callback is called.
+    pub(crate) rate_limit: Option<RateLimiter>,
+}
+
+#[cfg(feature = "memmap2")]
+impl<G, T, E> Callback for Mapped<G>
+where
+    G: FnMut(&Path, &[u8]) -> Result<T, E>,
+    E: From<std::io::Error>,
+{
+    type Output = T;
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        let file = File::open(path)?;
+
+        let len = file.metadata()?.len();
+
+        // Empty files can't be mapped
+        if len == 0 {
+            return (self.callback)(path, &[]);
+        }
+
+        if let Some(limiter) = &mut self.rate_limit {
+            limiter.consume(len);
+        }
+
+        // Safety: the map is only valid as long as no other process truncates or modifies the
+        // file. By the time a file is processed it has matured, so it's assumed to be complete.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+
+        // The map is dropped when this returns, before the watcher tries to delete the file.
+        (self.callback)(path, &map)
+    }
+}
+
+/// Extracts the named capture groups from each matured file's name and passes them to the
+/// wrapped closure.
+///
+/// Created by [Watcher::new_captures](crate::Watcher::new_captures).
+#[cfg(feature = "regex")]
+pub struct Captures<G> {
+    pub(crate) regex: regex::Regex,
+    pub(crate) callback: G,
+}
+
+#[cfg(feature = "regex")]
+impl<G, T, E> Callback for Captures<G>
+where
+    G: FnMut(&Path, HashMap<String, String>) -> Result<T, E>,
+{
+    type Output = T;
+    type Error = E;
+
+    fn call(&mut self, path: &Path) -> Result<T, E> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        let captures = match self.regex.captures(&name) {
+            Some(caps) => self
+                .regex
+                .capture_names()
+                .flatten()
+                .filter_map(|group| {
+                    Some((group.to_string(), caps.name(group)?.as_str().to_string()))
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        (self.callback)(path, captures)
+    }
+}
+
+/// Processes a matured file asynchronously, for [Watcher::watch_async](crate::Watcher::watch_async).
+///
+/// This is implemented for any `FnMut(PathBuf) -> Fut` closure whose future resolves to a
+/// `Result<T, E>`, which is what [Watcher::new_async](crate::Watcher::new_async) accepts. Unlike
+/// [Callback], it's given an owned path, so that its future can be spawned onto the runtime.
+#[cfg(feature = "tokio")]
+pub trait AsyncCallback {
+    /// The value produced for a successfully processed file
+    type Output;
+
+    /// The error produced for a file that couldn't be processed
+    type Error;
+
+    /// The future that processes a file
+    type Future: std::future::Future<Output = Result<Self::Output, Self::Error>>;
+
+    fn call(&mut self, path: PathBuf) -> Self::Future;
+}
+
+#[cfg(feature = "tokio")]
+impl<G, Fut, T, E> AsyncCallback for G
+where
+    G: FnMut(PathBuf) -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+{
+    type Output = T;
+    type Error = E;
+    type Future = Fut;
+
+    fn call(&mut self, path: PathBuf) -> Fut {
+        self(path)
+    }
+}
+
+/// Processes files with an [AsyncCallback], as created by
+/// [Watcher::new_async](crate::Watcher::new_async). It doesn't implement [Callback], so such a
+/// watcher can only be run by [Watcher::watch_async](crate::Watcher::watch_async):
+///
+/// ```compile_fail
+/// # use watch_files::{StopCondition, Watcher};
+/// let mut watcher = Watcher::new_async("*.txt", |path| async move { std::fs::read(path) });
+/// watcher.watch(StopCondition::Once);
+/// ```
+#[cfg(feature = "tokio")]
+pub struct Async<G> {
+    pub(crate) callback: G,
+
+    /// The maximum number of files processed at once
+    pub(crate) concurrency: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl<G: AsyncCallback> Handler for Async<G> {
+    type Output = G::Output;
+    type Error = G::Error;
+
+    fn call_event(&mut self, _event: &FileEvent) -> Result<G::Output, G::Error> {
+        unreachable!("watch_async defers every matured file rather than calling it in place")
+    }
+
+    fn companions(&self, _path: &Path) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    fn records(&self, _output: &G::Output) -> u64 {
+        1
+    }
+
+    fn should_retry(&self, _error: &G::Error) -> bool {
+        true
+    }
+
+    fn finished(&self, _path: &Path, _result: Result<&G::Output, &G::Error>) {}
+
+    fn skip_reason(&mut self) -> Option<SkipReason> {
+        None
+    }
+}
@@ -0,0 +1,126 @@
+use std::{fs::File, io::Read, path::Path};
+
+/// Algorithms available for hashing file contents
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChecksumAlgo {
+    /// CRC-32 (IEEE), as used by gzip and zip
+    Crc32,
+
+    /// 64-bit FNV-1a
+    Fnv1a64,
+
+    /// SHA-256
+    #[cfg(feature = "sha2")]
+    Sha256,
+}
+
+impl ChecksumAlgo {
+    /// Computes the lowercase hex digest of `bytes`
+    pub fn digest(self, bytes: &[u8]) -> String {
+        let mut hasher = Hasher::new(self);
+        hasher.update(bytes);
+        hasher.finish()
+    }
+
+    /// Computes the lowercase hex digest of the file at `path`, reading it in chunks
+    pub fn digest_file(self, path: &Path) -> Result<String, std::io::Error> {
+        let mut file = File::open(path)?;
+        let mut hasher = Hasher::new(self);
+        let mut buf = [0; 64 * 1024];
+
+        loop {
+            match file.read(&mut buf)? {
+                0 => return Ok(hasher.finish()),
+                n => hasher.update(&buf[..n]),
+            }
+        }
+    }
+}
+
+/// Adds the digest `hex` to `sum`, treating both as big-endian integers and wrapping on overflow.
+/// Because addition is commutative, the sum of several digests doesn't depend on their order.
+pub(crate) fn add_digest(sum: &mut Vec<u8>, hex: &str) {
+    let bytes: Vec<u8> = (0..hex.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+        .collect();
+
+    if sum.len() < bytes.len() {
+        sum.resize(bytes.len(), 0);
+    }
+
+    let mut carry = 0;
+    for (total, byte) in sum.iter_mut().rev().zip(bytes.iter().rev()) {
+        let added = *total as u16 + *byte as u16 + carry;
+        *total = added as u8;
+        carry = added >> 8;
+    }
+}
+
+enum Hasher {
+    Crc32(u32),
+    Fnv1a64(u64),
+    #[cfg(feature = "sha2")]
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    fn new(algo: ChecksumAlgo) -> Self {
+        match algo {
+            ChecksumAlgo::Crc32 => Hasher::Crc32(!0),
+            ChecksumAlgo::Fnv1a64 => Hasher::Fnv1a64(0xcbf29ce484222325),
+            #[cfg(feature = "sha2")]
+            ChecksumAlgo::Sha256 => Hasher::Sha256(<sha2::Sha256 as sha2::Digest>::new()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Crc32(crc) => {
+                for &b in bytes {
+                    *crc = CRC32_TABLE[((*crc ^ b as u32) & 0xff) as usize] ^ (*crc >> 8);
+                }
+            }
+            Hasher::Fnv1a64(hash) => {
+                for &b in bytes {
+                    *hash ^= b as u64;
+                    *hash = hash.wrapping_mul(0x100000001b3);
+                }
+            }
+            #[cfg(feature = "sha2")]
+            Hasher::Sha256(hasher) => sha2::Digest::update(hasher, bytes),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Crc32(crc) => format!("{:08x}", !crc),
+            Hasher::Fnv1a64(hash) => format!("{hash:016x}"),
+            #[cfg(feature = "sha2")]
+            Hasher::Sha256(hasher) => sha2::Digest::finalize(hasher)
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+        }
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
@@ -0,0 +1,315 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{ChecksumAlgo, ProcessOrder};
+
+/// All of a [Watcher](crate::Watcher)'s data-only settings in one place, so they can be loaded
+/// from a configuration file rather than set through individual builder methods.
+///
+/// With the `serde` feature, this can be deserialized from any serde-supported format. Missing
+/// fields take their default values, which are the same as those of a newly-created watcher.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct WatchConfig {
+    /// See [Watcher::check_duration](crate::Watcher::check_duration)
+    pub check_interval: Duration,
+
+    /// See [Watcher::rescan_interval](crate::Watcher::rescan_interval)
+    pub rescan_interval: Option<Duration>,
+
+    /// See [Watcher::exclude](crate::Watcher::exclude)
+    pub exclude: Vec<String>,
+
+    /// See [Watcher::ignore_extensions](crate::Watcher::ignore_extensions)
+    pub ignore_extensions: Vec<String>,
+
+    /// See [Watcher::wait_for_partials](crate::Watcher::wait_for_partials)
+    pub wait_for_partials: bool,
+
+    /// See [Watcher::maturation](crate::Watcher::maturation)
+    pub mature_after: Duration,
+
+    /// See [Watcher::mature_on_stable_size](crate::Watcher::mature_on_stable_size)
+    pub mature_on_stable_size: Option<Duration>,
+
+    /// See [Watcher::min_size](crate::Watcher::min_size)
+    pub min_size: Option<u64>,
+
+    /// See [Watcher::max_wait](crate::Watcher::max_wait)
+    pub max_wait: Option<Duration>,
+
+    /// See [Watcher::clamp_future_mtimes](crate::Watcher::clamp_future_mtimes)
+    pub clamp_future_mtimes: bool,
+
+    /// See [Watcher::finalize_wait](crate::Watcher::finalize_wait)
+    pub finalize_wait: Option<Duration>,
+
+    /// See [Watcher::delete_on_completion](crate::Watcher::delete_on_completion)
+    pub delete_on_completion: bool,
+
+    /// See [Watcher::delete_rules](crate::Watcher::delete_rules)
+    pub delete_rules: Vec<(String, bool)>,
+
+    /// See [Watcher::move_on_completion](crate::Watcher::move_on_completion)
+    pub move_on_completion: Option<PathBuf>,
+
+    /// See [Watcher::delete_after](crate::Watcher::delete_after)
+    pub delete_after: Option<Duration>,
+
+    /// See [Watcher::verify_consumed](crate::Watcher::verify_consumed)
+    pub verify_consumed: bool,
+
+    /// See [Watcher::forget_consumed](crate::Watcher::forget_consumed)
+    pub forget_consumed: bool,
+
+    /// See [Watcher::claim](crate::Watcher::claim)
+    pub claim: bool,
+
+    /// See [Watcher::catch_panics](crate::Watcher::catch_panics)
+    pub catch_panics: bool,
+
+    /// See [Watcher::streaming](crate::Watcher::streaming)
+    pub streaming: bool,
+
+    /// See [Watcher::track_by_inode](crate::Watcher::track_by_inode)
+    pub track_by_inode: bool,
+
+    /// See [Watcher::only_new](crate::Watcher::only_new)
+    pub only_new: bool,
+
+    /// See [Watcher::reprocess_on_change](crate::Watcher::reprocess_on_change)
+    pub reprocess_on_change: bool,
+
+    /// See [Watcher::max_attempts](crate::Watcher::max_attempts)
+    pub max_attempts: u32,
+
+    /// See [Watcher::retry](crate::Watcher::retry)
+    pub retry_backoff: Duration,
+
+    /// See [Watcher::min_batch](crate::Watcher::min_batch)
+    pub min_batch: usize,
+
+    /// See [Watcher::order](crate::Watcher::order)
+    pub order: ProcessOrder,
+
+    /// See [Watcher::require_trailing](crate::Watcher::require_trailing)
+    pub require_trailing: Option<Vec<u8>>,
+
+    /// See [Watcher::require_size_sidecar](crate::Watcher::require_size_sidecar)
+    pub require_size_sidecar: Option<String>,
+
+    /// See [Watcher::require_exclusive_access](crate::Watcher::require_exclusive_access)
+    pub require_exclusive_access: bool,
+
+    /// See [Watcher::write_completion_marker](crate::Watcher::write_completion_marker)
+    pub write_completion_marker: Option<String>,
+
+    /// See [Watcher::write_failure_marker](crate::Watcher::write_failure_marker)
+    pub write_failure_marker: Option<String>,
+
+    /// See [Watcher::hash_completed](crate::Watcher::hash_completed)
+    pub hash_completed: Option<ChecksumAlgo>,
+
+    /// See [Watcher::dedup_lru](crate::Watcher::dedup_lru)
+    pub dedup_lru: Option<usize>,
+
+    /// See [Watcher::combined_digest](crate::Watcher::combined_digest)
+    pub combined_digest: Option<ChecksumAlgo>,
+
+    /// See [Watcher::max_size_fraction_of_ram](crate::Watcher::max_size_fraction_of_ram)
+    pub max_size_fraction_of_ram: Option<f64>,
+
+    /// See [Watcher::warn_after_files](crate::Watcher::warn_after_files)
+    pub warn_after_files: Option<usize>,
+
+    /// See [Watcher::warn_after_duration](crate::Watcher::warn_after_duration)
+    pub warn_after_duration: Option<Duration>,
+
+    /// See [Watcher::max_tracked_files](crate::Watcher::max_tracked_files)
+    pub max_tracked_files: Option<usize>,
+
+    /// See [Watcher::verbose](crate::Watcher::verbose)
+    pub verbose: bool,
+
+    /// See [Watcher::dry_run](crate::Watcher::dry_run)
+    pub dry_run: bool,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            check_interval: Duration::from_secs(1),
+            rescan_interval: None,
+            exclude: Vec::new(),
+            ignore_extensions: Vec::new(),
+            wait_for_partials: false,
+            mature_after: Duration::from_secs(5),
+            mature_on_stable_size: None,
+            min_size: None,
+            max_wait: None,
+            clamp_future_mtimes: false,
+            finalize_wait: None,
+            delete_on_completion: false,
+            delete_rules: Vec::new(),
+            move_on_completion: None,
+            delete_after: None,
+            verify_consumed: false,
+            forget_consumed: false,
+            claim: false,
+            catch_panics: false,
+            streaming: false,
+            track_by_inode: false,
+            only_new: false,
+            reprocess_on_change: false,
+            max_attempts: 1,
+            retry_backoff: Duration::ZERO,
+            min_batch: 1,
+            order: ProcessOrder::Fifo,
+            require_trailing: None,
+            require_size_sidecar: None,
+            require_exclusive_access: false,
+            write_completion_marker: None,
+            write_failure_marker: None,
+            hash_completed: None,
+            combined_digest: None,
+            dedup_lru: None,
+            max_size_fraction_of_ram: None,
+            warn_after_files: None,
+            warn_after_duration: None,
+            max_tracked_files: None,
+            verbose: false,
+            dry_run: false,
+        }
+    }
+}
+
+impl WatchConfig {
+    /// Checks that all values are usable, returning the first problem found
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.check_interval.is_zero() {
+            return Err(ConfigError::new(
+                "check_interval",
+                "must be greater than zero",
+            ));
+        }
+
+        if self.rescan_interval.is_some_and(|d| d.is_zero()) {
+            return Err(ConfigError::new(
+                "rescan_interval",
+                "must be greater than zero",
+            ));
+        }
+
+        if self
+            .exclude
+            .iter()
+            .any(|pattern| glob::Pattern::new(pattern).is_err())
+        {
+            return Err(ConfigError::new("exclude", "must be valid glob patterns"));
+        }
+
+        if self
+            .delete_rules
+            .iter()
+            .any(|(pattern, _)| glob::Pattern::new(pattern).is_err())
+        {
+            return Err(ConfigError::new(
+                "delete_rules",
+                "must be valid glob patterns",
+            ));
+        }
+
+        if self.max_attempts == 0 {
+            return Err(ConfigError::new("max_attempts", "must be at least 1"));
+        }
+
+        if self.min_batch == 0 {
+            return Err(ConfigError::new("min_batch", "must be at least 1"));
+        }
+
+        if self
+            .require_trailing
+            .as_ref()
+            .is_some_and(|trailer| trailer.is_empty())
+        {
+            return Err(ConfigError::new("require_trailing", "must not be empty"));
+        }
+
+        if self
+            .require_size_sidecar
+            .as_ref()
+            .is_some_and(|suffix| suffix.is_empty())
+        {
+            return Err(ConfigError::new(
+                "require_size_sidecar",
+                "must not be empty",
+            ));
+        }
+
+        if self
+            .write_completion_marker
+            .as_ref()
+            .is_some_and(|suffix| suffix.is_empty())
+        {
+            return Err(ConfigError::new(
+                "write_completion_marker",
+                "must not be empty",
+            ));
+        }
+
+        if self
+            .write_failure_marker
+            .as_ref()
+            .is_some_and(|suffix| suffix.is_empty())
+        {
+            return Err(ConfigError::new(
+                "write_failure_marker",
+                "must not be empty",
+            ));
+        }
+
+        if self.dedup_lru == Some(0) {
+            return Err(ConfigError::new("dedup_lru", "must be at least 1"));
+        }
+
+        if self.max_tracked_files == Some(0) {
+            return Err(ConfigError::new("max_tracked_files", "must be at least 1"));
+        }
+
+        if self
+            .max_size_fraction_of_ram
+            .is_some_and(|f| !(f > 0.0 && f.is_finite()))
+        {
+            return Err(ConfigError::new(
+                "max_size_fraction_of_ram",
+                "must be a positive number",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// An invalid value in a [WatchConfig]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    /// The name of the offending field
+    pub field: &'static str,
+
+    /// Why the value isn't valid
+    pub reason: &'static str,
+}
+
+impl ConfigError {
+    fn new(field: &'static str, reason: &'static str) -> Self {
+        ConfigError { field, reason }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid {}: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+/// A problem with a watcher's source that prevents it from watching, as reported by
+/// [Watcher::try_watch](crate::Watcher::try_watch)
+#[derive(Debug)]
+pub enum WatchError {
+    /// A glob pattern couldn't be parsed
+    InvalidPattern {
+        pattern: String,
+        error: glob::PatternError,
+    },
+
+    /// A directory that files are listed from couldn't be read
+    Inaccessible { dir: PathBuf, error: std::io::Error },
+}
+
+impl std::fmt::Display for WatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchError::InvalidPattern { pattern, error } => {
+                write!(f, "invalid pattern {pattern:?}: {error}")
+            }
+            WatchError::Inaccessible { dir, error } => {
+                write!(f, "couldn't read {}: {error}", dir.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for WatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WatchError::InvalidPattern { error, .. } => Some(error),
+            WatchError::Inaccessible { error, .. } => Some(error),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver},
+};
+
+use notify::{EventKind, RecursiveMode, Watcher as _};
+
+/// Filesystem change notifications for the directories a source lists files from, used to skip
+/// listing them when nothing has changed
+pub(crate) struct Events {
+    // Kept alive so that notifications keep arriving
+    _watcher: notify::RecommendedWatcher,
+    receiver: Receiver<notify::Result<notify::Event>>,
+}
+
+impl Events {
+    /// Starts watching `roots`, each paired with whether its subdirectories are watched too.
+    /// Returns an error if any of them can't be watched.
+    pub(crate) fn new(roots: &[(PathBuf, bool)]) -> notify::Result<Self> {
+        let (sender, receiver) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        for (root, recursive) in roots {
+            let mode = if *recursive {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            watcher.watch(root, mode)?;
+        }
+
+        Ok(Events {
+            _watcher: watcher,
+            receiver,
+        })
+    }
+
+    /// Whether anything has changed since this was last called. Errors count as changes, since
+    /// the watcher may have missed an event.
+    pub(crate) fn changed(&self) -> bool {
+        let mut changed = false;
+        for event in self.receiver.try_iter() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+                _ => changed = true,
+            }
+        }
+        changed
+    }
+}
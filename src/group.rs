@@ -0,0 +1,136 @@
+use std::{
+    any::Any,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use crate::FileResults;
+
+/// A cloneable signal that tells one or more watchers to stop at their next check.
+///
+/// Pass a handle to [Watcher::with_cancel](crate::Watcher::with_cancel), then call
+/// [StopHandle::stop] from any thread. Watchers that stop this way return the results
+/// accumulated so far, same as if their [StopCondition](crate::StopCondition) had been met.
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signals all watchers sharing this handle to stop
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [stop](Self::stop) has been called on this or any cloned handle
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Wraps an existing flag, such as one set by a signal handler. Storing `true` in it stops the
+/// watchers sharing the handle.
+impl From<Arc<AtomicBool>> for StopHandle {
+    fn from(flag: Arc<AtomicBool>) -> Self {
+        StopHandle(flag)
+    }
+}
+
+/// Runs several independent watchers, each on its own thread, that share a single [StopHandle].
+///
+/// Because each watcher may produce different result types, [join](Self::join) returns the
+/// results type-erased, in the order the watchers were spawned. Use
+/// [downcast](Box::downcast) to recover the concrete `FileResults<T, E>`:
+///
+/// ```no_run
+/// # use watch_files::{FileResults, StopCondition, Watcher, WatcherGroup};
+/// # use std::time::Duration;
+/// let mut group = WatcherGroup::new();
+/// group.spawn(|handle| {
+///     Watcher::new("*.txt", |path| std::fs::read_to_string(path))
+///         .with_cancel(handle)
+///         .watch(StopCondition::Elapsed(Duration::MAX))
+/// });
+///
+/// // ... later
+/// group.stop();
+/// let results = group.join();
+/// let text = results[0]
+///     .downcast_ref::<FileResults<String, std::io::Error>>()
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct WatcherGroup {
+    handle: StopHandle,
+    threads: Vec<JoinHandle<Box<dyn Any + Send>>>,
+}
+
+impl WatcherGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns a thread that runs `watch`, passing it the group's [StopHandle].
+    ///
+    /// The watcher is constructed inside the spawned thread, so its callback needn't be `Send`.
+    pub fn spawn<G, T, E>(&mut self, watch: G)
+    where
+        G: FnOnce(StopHandle) -> FileResults<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let handle = self.handle.clone();
+        let thread = std::thread::spawn(move || {
+            // Stop the other watchers right away, rather than once this thread is joined, since
+            // `join` may be waiting on one of them
+            let results = std::panic::catch_unwind(AssertUnwindSafe(|| watch(handle.clone())))
+                .unwrap_or_else(|payload| {
+                    handle.stop();
+                    std::panic::resume_unwind(payload)
+                });
+            Box::new(results) as Box<dyn Any + Send>
+        });
+        self.threads.push(thread);
+    }
+
+    /// Returns a handle that stops every watcher in this group
+    pub fn stop_handle(&self) -> StopHandle {
+        self.handle.clone()
+    }
+
+    /// Signals every watcher in this group to stop
+    pub fn stop(&self) {
+        self.handle.stop();
+    }
+
+    /// Waits for all watchers to finish, returning their results in the order they were spawned.
+    ///
+    /// If a watcher panicked, the group is stopped and the panic is propagated once the remaining
+    /// watchers have finished.
+    pub fn join(self) -> Vec<Box<dyn Any + Send>> {
+        let mut results = Vec::with_capacity(self.threads.len());
+        let mut panic = None;
+
+        for thread in self.threads {
+            match thread.join() {
+                Ok(result) => results.push(result),
+                Err(payload) => {
+                    self.handle.stop();
+                    panic.get_or_insert(payload);
+                }
+            }
+        }
+
+        if let Some(payload) = panic {
+            std::panic::resume_unwind(payload);
+        }
+
+        results
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Patterns loaded from a gitignore-style file, reloaded whenever the file changes.
+///
+/// Supported syntax is a subset of `.gitignore`: blank lines and lines starting with `#` are
+/// ignored, `!` negates a pattern, a trailing `/` matches only directories, and a pattern
+/// containing a `/` is matched against the path relative to the ignore file's directory rather
+/// than against individual names. The last matching pattern wins.
+pub(crate) struct IgnoreFile {
+    path: PathBuf,
+
+    /// The ignore file's modification time when it was last loaded, if it exists
+    modified: Option<SystemTime>,
+
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: glob::Pattern,
+
+    /// Whether a match un-ignores the path
+    negated: bool,
+
+    /// Whether only directories match
+    dir_only: bool,
+
+    /// Whether the pattern is matched against relative paths rather than names
+    has_slash: bool,
+}
+
+impl IgnoreFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        let mut ignore = IgnoreFile {
+            path,
+            modified: None,
+            rules: Vec::new(),
+        };
+        ignore.refresh();
+        ignore
+    }
+
+    /// The ignore file's own path
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reloads the patterns if the ignore file has been modified, created, or removed since they
+    /// were last loaded
+    pub(crate) fn refresh(&mut self) {
+        let modified = self.path.metadata().and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return;
+        }
+
+        self.modified = modified;
+        self.rules = std::fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(Rule::parse)
+            .collect();
+    }
+
+    /// Whether `path` is excluded by the loaded patterns
+    pub(crate) fn is_ignored(&self, path: &Path) -> bool {
+        if path == self.path {
+            return true;
+        }
+
+        let base = self.path.parent().unwrap_or(Path::new(""));
+        let relative = path.strip_prefix(base).unwrap_or(path);
+        let components: Vec<_> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+
+        let is_dir = self.rules.iter().any(|rule| rule.dir_only) && path.is_dir();
+
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.matches(&components, is_dir) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let has_slash = line.contains('/');
+        let line = line.trim_start_matches('/');
+
+        Some(Rule {
+            pattern: glob::Pattern::new(line).ok()?,
+            negated,
+            dir_only,
+            has_slash,
+        })
+    }
+
+    /// Whether this rule matches a path made up of `components`, or any directory containing it
+    fn matches(&self, components: &[std::borrow::Cow<str>], is_dir: bool) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+
+        // The last component is the path itself; the rest are its directories
+        let candidates = if self.dir_only && !is_dir {
+            components.len().saturating_sub(1)
+        } else {
+            components.len()
+        };
+
+        (0..candidates).any(|i| {
+            if self.has_slash {
+                let prefix = components[..=i].join("/");
+                self.pattern.matches_with(&prefix, options)
+            } else {
+                self.pattern.matches_with(&components[i], options)
+            }
+        })
+    }
+}
@@ -1,26 +1,129 @@
+mod callback;
+mod checksum;
+mod config;
+mod error;
+#[cfg(feature = "notify")]
+mod events;
+mod group;
+mod ignore;
+mod output;
+mod source;
+mod throttle;
 mod watcher;
-pub use watcher::Watcher;
+pub use callback::{
+    Aggregate, Callback, Counted, Detect, Discard, FileEvent, Header, OnError, Reader, ResultSink,
+    RetryIf, Ticked, Timeout, WithFile, WithMetadata, WithSink,
+};
+#[cfg(feature = "tokio")]
+pub use callback::{Async, AsyncCallback};
+#[cfg(feature = "regex")]
+pub use callback::Captures;
+#[cfg(feature = "memmap2")]
+pub use callback::Mapped;
+pub use checksum::ChecksumAlgo;
+pub use config::{ConfigError, WatchConfig};
+pub use error::WatchError;
+pub use group::{StopHandle, WatcherGroup};
+pub use watcher::{WatchIter, Watcher};
 
 use std::{
     collections::HashMap,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{Duration, SystemTime},
 };
 
 /// Specifies how a watcher will stop monitoring files
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum StopCondition {
     /// Looks for matching files once, stopping execution immediately after processing all files
+    /// that have matured. Files that haven't matured yet are reported in
+    /// [FileResults::not_processed], and files that appear afterward are ignored, so this
+    /// finishes known work without accepting new work. Unless [Watcher::finalize_wait] is set,
+    /// the source is only listed once.
     Once,
 
     /// Continues watching until the specified number of files have been found
     FilesFound(usize),
 
+    /// Continues watching until callbacks have reported producing at least the specified number of
+    /// records. See [Watcher::new_counted].
+    RecordsFound(u64),
+
     /// Continues watching until the specified duration of time has elapsed
     Elapsed(Duration),
 
-    /// Continues watching until the specified duration has elapsed without a new file
+    /// Continues watching until the specified duration has elapsed without a new file being
+    /// found. This is measured from when the watcher first saw the most recently found file, or
+    /// from when watching started if no file has been found, regardless of files' modification
+    /// times. Files that already exist when watching starts are found by the first check.
     NoNewFilesSince(Duration),
+
+    /// Continues watching until any of the conditions is met, such as a number of files or a
+    /// duration, whichever comes first. An empty list is never met.
+    Any(Vec<StopCondition>),
+
+    /// Continues watching until all of the conditions are met. An empty list is met immediately.
+    All(Vec<StopCondition>),
+}
+
+impl StopCondition {
+    /// Whether this condition has been met given the watcher's current progress
+    pub fn is_satisfied(&self, ctx: &StopContext) -> bool {
+        match self {
+            StopCondition::Once => true,
+            StopCondition::FilesFound(n) => ctx.completed >= *n,
+            StopCondition::RecordsFound(n) => ctx.records >= *n,
+            StopCondition::Elapsed(d) => ctx.elapsed >= *d,
+            StopCondition::NoNewFilesSince(d) => ctx.newest_file_age >= *d,
+            StopCondition::Any(conditions) => conditions.iter().any(|c| c.is_satisfied(ctx)),
+            StopCondition::All(conditions) => conditions.iter().all(|c| c.is_satisfied(ctx)),
+        }
+    }
+}
+
+/// A snapshot of a watcher's progress, used to evaluate a [StopCondition]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StopContext {
+    /// The number of files that have been successfully processed
+    pub completed: usize,
+
+    /// The number of records that processed files have produced. Each file counts as one record
+    /// unless its callback reports otherwise.
+    pub records: u64,
+
+    /// How long the watcher has been running
+    pub elapsed: Duration,
+
+    /// How long ago the watcher first saw the most recently found file, or how long it has been
+    /// running if it hasn't found any
+    pub newest_file_age: Duration,
+}
+
+/// A snapshot of a watcher's progress at the end of a check, passed to [Watcher::on_tick]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TickInfo {
+    /// The number of the check that just finished, counting from 1
+    pub tick: u64,
+
+    /// The number of files found so far, whatever their status
+    pub seen: usize,
+
+    /// The number of files the watcher is currently keeping state for, whatever their status.
+    /// Unlike [seen](Self::seen), this doesn't include files that were
+    /// [forgotten](Watcher::forget_consumed).
+    pub tracked: usize,
+
+    /// The number of files waiting to mature or to be retried
+    pub pending: usize,
+
+    /// The number of files that have been successfully processed
+    pub completed: usize,
+
+    /// The number of files whose processing failed
+    pub errored: usize,
+
+    /// How long the watcher has been running
+    pub elapsed: Duration,
 }
 
 #[derive(Debug)]
@@ -28,6 +131,96 @@ enum FileStatus<T,E> {
     ProcessingCompleted(T),
     Processing(SystemTime),
     Error(E),
+    Skipped(SkipReason),
+    /// Processed, but waiting for the file to be deleted externally. Holds the file's
+    /// modification time when it was processed.
+    AwaitingAck(T, SystemTime),
+    /// Matured during a [dry run](Watcher::dry_run), so wasn't passed to the callback
+    WouldProcess,
+}
+
+/// How a watcher handles an error reading a file's metadata, as decided by
+/// [Watcher::metadata_error_policy]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Ignores the error and tries the file again at the next check. If the file is still failing
+    /// when watching stops, its latest error is recorded in [FileResults::errored].
+    Retry,
+
+    /// Records the error in [FileResults::errored] and doesn't try the file again
+    Skip,
+
+    /// Records the error and stops watching, as if the [StopCondition] had been met
+    Abort,
+}
+
+/// The order in which files that matured during the same check are processed, as set by
+/// [Watcher::order]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProcessOrder {
+    /// The file that was first seen earliest is processed first
+    #[default]
+    Fifo,
+
+    /// The file that was first seen most recently is processed first
+    Lifo,
+
+    /// The file with the oldest modification time is processed first
+    OldestModifiedFirst,
+
+    /// The file with the newest modification time is processed first
+    NewestModifiedFirst,
+
+    /// The smallest file is processed first
+    SmallestFirst,
+
+    /// The largest file is processed first
+    LargestFirst,
+}
+
+/// Why a file was intentionally not processed
+#[derive(Debug)]
+pub enum SkipReason {
+    /// The file was larger than the limit set by [Watcher::max_size_fraction_of_ram]
+    TooLargeForMemory { size: u64, limit: u64 },
+
+    /// The file had already been processed under the path `from` and was since renamed, as
+    /// detected by [Watcher::track_by_inode]
+    Renamed { from: PathBuf },
+
+    /// The file's contents were identical to those of `of`, which was recently processed, as
+    /// detected by [Watcher::dedup_lru]
+    Duplicate { of: PathBuf },
+
+    /// The file couldn't be opened to pass it to the callback given to
+    /// [Watcher::with_file_callback]
+    OpenFailed { error: std::io::Error },
+
+    /// The file matched a pattern in the [ignore file](Watcher::ignore_file)
+    Ignored,
+}
+
+/// Something a watcher did, as reported to the channel given to [Watcher::events]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// The file was found and is now tracked while it matures
+    Seen(PathBuf),
+
+    /// The file matured and is about to be processed
+    Mature(PathBuf),
+
+    /// The file was processed successfully
+    Completed(PathBuf),
+
+    /// The file failed and won't be retried
+    Errored(PathBuf),
+
+    /// The file was intentionally not processed; see [SkipReason]
+    Skipped(PathBuf),
+
+    /// The file was deleted after being processed
+    Deleted(PathBuf),
 }
 
 pub struct FileResults<T, E> {
@@ -39,9 +232,176 @@ pub struct FileResults<T, E> {
     /// was hit before they could mature.
     pub not_processed: Vec<PathBuf>,
 
+    /// Files that matured during a [dry run](Watcher::dry_run), and so would otherwise have been
+    /// processed, in the order they matured
+    pub would_process: Vec<PathBuf>,
+
     /// Files that were not processed due to an error.
     /// 
     /// The user-specified closure can return `E` or the watcher 
     /// itself can return std::io::Error if metadata can't be fetched.
     pub errored: std::collections::HashMap<PathBuf, E>,
+
+    /// Files that were intentionally not processed, such as those exceeding a size limit.
+    pub skipped: HashMap<PathBuf, SkipReason>,
+
+    /// Digests of successfully processed files, populated when
+    /// [Watcher::hash_completed] is set.
+    pub hashes: HashMap<PathBuf, String>,
+
+    /// A single digest over the contents of all successfully processed files, populated when
+    /// [Watcher::combined_digest] is set and at least one file was processed.
+    pub combined_hash: Option<String>,
+
+    /// Files successfully processed but not yet deleted by their consumer when watching stopped,
+    /// populated when [Watcher::require_ack] is set.
+    pub awaiting_ack: HashMap<PathBuf, T>,
+
+    /// Files deleted after being processed, whether immediately or once their
+    /// [retention period](Watcher::delete_after) passed. Companion files are included. Empty if
+    /// [Watcher::forget_consumed] is set.
+    pub deleted: Vec<PathBuf>,
+
+    /// Files that should have been deleted after being processed but couldn't be, and so are
+    /// likely still on disk. Companion files are included. A file's result is still recorded in
+    /// [completed](Self::completed), since it was processed successfully.
+    pub cleanup_failures: HashMap<PathBuf, std::io::Error>,
+
+    /// Whether any file matched the watcher's pattern at any point. When this is `false`, the
+    /// empty results likely indicate a mistyped pattern or wrong directory rather than there
+    /// simply being no work to do.
+    pub matched_any: bool,
+
+    /// The number of records produced by all successfully processed files. Each file counts as
+    /// one record unless its callback reports otherwise, as with [Watcher::new_counted].
+    pub total_records: u64,
+
+    /// The number of successfully processed files that were deleted or moved away and then
+    /// dropped from these results, if [Watcher::forget_consumed] is set.
+    pub forgotten: usize,
+
+    /// The number of times the callback was called for each file, which is more than one for
+    /// files that were [retried](Watcher::retry).
+    pub attempts: HashMap<PathBuf, u32>,
+
+    /// The order in which each processed file's result was produced, counting from 0, whether it
+    /// succeeded, failed or was skipped. A file that was processed again has its latest position.
+    /// See [completed_in_order](Self::completed_in_order).
+    pub sequence: HashMap<PathBuf, u64>,
+
+    /// Total time spent discovering files and checking their metadata
+    pub scan_time: Duration,
+
+    /// Total time spent running the callback on matured files
+    pub process_time: Duration,
+}
+
+impl<T, E> FileResults<T, E> {
+    /// Groups [errored](Self::errored) files by their error's [Display](std::fmt::Display) text, counting how many
+    /// files failed with each error.
+    pub fn error_summary(&self) -> HashMap<String, usize>
+    where
+        E: std::fmt::Display,
+    {
+        self.error_summary_by(|e| e.to_string())
+    }
+
+    /// The [completed](Self::completed) files found under `base`, such as one of the directories
+    /// passed to [Watcher::base_dirs].
+    pub fn completed_under<'a>(
+        &'a self,
+        base: &'a Path,
+    ) -> impl Iterator<Item = (&'a PathBuf, &'a T)> {
+        self.completed
+            .iter()
+            .filter(move |(path, _)| path.starts_with(base))
+    }
+
+    /// The [completed](Self::completed) files in the order they were processed
+    pub fn completed_in_order(&self) -> Vec<(&PathBuf, &T)> {
+        let mut completed: Vec<_> = self.completed.iter().collect();
+        completed.sort_by_key(|(path, _)| self.sequence.get(*path));
+        completed
+    }
+
+    /// Groups [errored](Self::errored) files by the key that `key` derives from their error,
+    /// counting how many files failed with each key.
+    pub fn error_summary_by<K>(&self, key: K) -> HashMap<String, usize>
+    where
+        K: Fn(&E) -> String,
+    {
+        let mut summary = HashMap::new();
+        for e in self.errored.values() {
+            *summary.entry(key(e)).or_insert(0) += 1;
+        }
+        summary
+    }
+
+    /// The number of files in any of the results: succeeded, failed, skipped, not processed, or
+    /// that would have been processed during a dry run
+    pub fn total(&self) -> usize {
+        self.success_count()
+            + self.error_count()
+            + self.skipped_count()
+            + self.not_processed_count()
+            + self.would_process.len()
+    }
+
+    /// The number of files successfully processed, including those
+    /// [awaiting acknowledgement](Self::awaiting_ack) and those [forgotten](Self::forgotten)
+    pub fn success_count(&self) -> usize {
+        self.completed.len() + self.awaiting_ack.len() + self.forgotten
+    }
+
+    /// The number of files that failed
+    pub fn error_count(&self) -> usize {
+        self.errored.len()
+    }
+
+    /// The number of files that were intentionally not processed
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.len()
+    }
+
+    /// The number of files that hadn't matured when watching stopped
+    pub fn not_processed_count(&self) -> usize {
+        self.not_processed.len()
+    }
+
+    /// The fraction of files that either succeeded or failed that succeeded, from 0 to 1, or 0 if
+    /// no files were processed
+    pub fn success_rate(&self) -> f64 {
+        let processed = self.success_count() + self.error_count();
+        if processed == 0 {
+            0.0
+        } else {
+            self.success_count() as f64 / processed as f64
+        }
+    }
+
+    /// A one-line description of how many files ended up in each result, such as
+    /// `10 succeeded, 1 failed, 0 skipped, 2 not processed`, followed by how many files would
+    /// have been processed for a dry run. This is also how the results are
+    /// [displayed](std::fmt::Display).
+    pub fn summary(&self) -> String {
+        let summary = format!(
+            "{} succeeded, {} failed, {} skipped, {} not processed",
+            self.success_count(),
+            self.error_count(),
+            self.skipped_count(),
+            self.not_processed_count()
+        );
+
+        if self.would_process.is_empty() {
+            summary
+        } else {
+            format!("{summary}, {} would be processed", self.would_process.len())
+        }
+    }
+}
+
+impl<T, E> std::fmt::Display for FileResults<T, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.summary())
+    }
 }
@@ -0,0 +1,31 @@
+/// Reports progress, such as a file being processed or deleted
+macro_rules! log_info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::info!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        println!($($arg)*);
+    }};
+}
+
+/// Reports something unexpected that the watcher can carry on from
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        eprintln!($($arg)*);
+    }};
+}
+
+/// Reports a failure to do something that was asked for, such as deleting a processed file
+macro_rules! log_error {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::error!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        eprintln!($($arg)*);
+    }};
+}
+
+pub(crate) use {log_error, log_info, log_warn};
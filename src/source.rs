@@ -0,0 +1,268 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::WatchError;
+
+/// Where a watcher looks for candidate files
+#[derive(Debug)]
+pub(crate) enum Source {
+    /// Files matching any of several glob patterns
+    Glob(Vec<String>),
+
+    /// Files matching any of several glob patterns relative to each of several base directories
+    Bases {
+        bases: Vec<PathBuf>,
+        patterns: Vec<String>,
+    },
+
+    /// Files directly within `dir` whose names match `regex`
+    #[cfg(feature = "regex")]
+    Regex { dir: PathBuf, regex: regex::Regex },
+
+    /// Files within `root` up to `max_depth` levels deep, where files directly within `root` are
+    /// at depth 1
+    #[cfg(feature = "walkdir")]
+    Dir {
+        root: PathBuf,
+        max_depth: Option<usize>,
+    },
+}
+
+impl Source {
+    /// Files matching `pattern`, with its braces expanded
+    pub(crate) fn glob(pattern: &str) -> Source {
+        Source::Glob(expand_braces(pattern))
+    }
+
+    /// Lists the files currently matching this source. Glob patterns are matched using `options`.
+    ///
+    /// The iterator doesn't borrow the source, so files can be processed while it's in use.
+    pub(crate) fn paths(&self, options: glob::MatchOptions) -> Box<dyn Iterator<Item = PathBuf>> {
+        match self {
+            Source::Glob(_) | Source::Bases { .. } => globs(self.glob_patterns(), options),
+            #[cfg(feature = "regex")]
+            Source::Regex { dir, regex } => {
+                let Ok(entries) = std::fs::read_dir(dir) else {
+                    return Box::new(std::iter::empty());
+                };
+
+                let regex = regex.clone();
+                Box::new(
+                    entries
+                        .flatten()
+                        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+                        .filter(move |entry| regex.is_match(&entry.file_name().to_string_lossy()))
+                        .map(|entry| entry.path()),
+                )
+            }
+            #[cfg(feature = "walkdir")]
+            Source::Dir { root, max_depth } => Box::new(
+                walkdir::WalkDir::new(root)
+                    .min_depth(1)
+                    .max_depth(max_depth.unwrap_or(usize::MAX))
+                    .into_iter()
+                    .flatten()
+                    .filter(|entry| entry.file_type().is_file())
+                    .map(walkdir::DirEntry::into_path),
+            ),
+        }
+    }
+
+    /// Checks that every glob pattern is valid and every directory that files are listed from
+    /// directly can be read
+    pub(crate) fn check(&self) -> Result<(), WatchError> {
+        for pattern in self.glob_patterns() {
+            if let Err(error) = glob::Pattern::new(&pattern) {
+                return Err(WatchError::InvalidPattern { pattern, error });
+            }
+        }
+
+        let dirs: Vec<&PathBuf> = match self {
+            Source::Glob(_) => Vec::new(),
+            Source::Bases { bases, .. } => bases.iter().collect(),
+            #[cfg(feature = "regex")]
+            Source::Regex { dir, .. } => vec![dir],
+            #[cfg(feature = "walkdir")]
+            Source::Dir { root, .. } => vec![root],
+        };
+        for dir in dirs {
+            if let Err(error) = std::fs::read_dir(dir) {
+                return Err(WatchError::Inaccessible {
+                    dir: dir.clone(),
+                    error,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The glob patterns that files are listed with, with each base directory prepended to each
+    /// pattern
+    fn glob_patterns(&self) -> Vec<String> {
+        match self {
+            Source::Glob(patterns) => patterns.clone(),
+            Source::Bases { bases, patterns } => bases
+                .iter()
+                .flat_map(|base| {
+                    // The base is used literally, even if it contains glob metacharacters
+                    let base = glob::Pattern::escape(&base.to_string_lossy());
+                    patterns.iter().map(move |pattern| {
+                        Path::new(&base)
+                            .join(pattern)
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                })
+                .collect(),
+            #[cfg(feature = "regex")]
+            Source::Regex { .. } => Vec::new(),
+            #[cfg(feature = "walkdir")]
+            Source::Dir { .. } => Vec::new(),
+        }
+    }
+
+    /// The directories that files matching this source can be found in, each paired with
+    /// whether files can be in its subdirectories too
+    #[cfg(feature = "notify")]
+    pub(crate) fn roots(&self) -> Vec<(PathBuf, bool)> {
+        match self {
+            Source::Glob(patterns) => patterns
+                .iter()
+                .map(|pattern| glob_root(Path::new(pattern)))
+                .collect(),
+            Source::Bases { bases, patterns } => bases
+                .iter()
+                .flat_map(|base| {
+                    patterns.iter().map(move |pattern| {
+                        let (root, recursive) = glob_root(Path::new(pattern));
+                        (base.join(root), recursive)
+                    })
+                })
+                .collect(),
+            #[cfg(feature = "regex")]
+            Source::Regex { dir, .. } => vec![(dir.clone(), false)],
+            #[cfg(feature = "walkdir")]
+            Source::Dir { root, max_depth } => vec![(root.clone(), *max_depth != Some(1))],
+        }
+    }
+}
+
+/// Expands each `{a,b,...}` group in `pattern` into separate patterns, as a shell would (eg,
+/// `*.{png,jpg}` becomes `*.png` and `*.jpg`). Groups may be nested. Braces within a character
+/// class like `[{]`, and groups without a comma, are left as they are.
+pub(crate) fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some((open, close, commas)) = find_group(pattern) else {
+        return vec![pattern.to_string()];
+    };
+
+    let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+    let bounds: Vec<usize> = std::iter::once(open)
+        .chain(commas)
+        .chain(std::iter::once(close))
+        .collect();
+
+    // Later groups, and any nested within an alternative, are expanded by recursing
+    bounds
+        .windows(2)
+        .flat_map(|bound| {
+            let alternative = &pattern[bound[0] + 1..bound[1]];
+            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+        })
+        .collect()
+}
+
+/// Finds the first brace group in `pattern` that has alternatives, returning the positions of
+/// its opening and closing braces and of the commas separating its alternatives
+fn find_group(pattern: &str) -> Option<(usize, usize, Vec<usize>)> {
+    let bytes = pattern.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'[' => i = class_end(bytes, i),
+            b'{' => {
+                if let Some((close, commas)) = match_group(bytes, i) {
+                    return Some((i, close, commas));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The closing brace of the group opened at `open` and the commas directly within it, if it's
+/// closed and has at least one comma
+fn match_group(bytes: &[u8], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = open + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'[' => i = class_end(bytes, i),
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return (!commas.is_empty()).then_some((i, commas)),
+            b'}' => depth -= 1,
+            b',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The position of the `]` closing the character class opened at `open`, or `open` itself if
+/// the class isn't closed. As with globs, a `]` right after the opening `[` or `[!` is part of
+/// the class.
+fn class_end(bytes: &[u8], open: usize) -> usize {
+    let mut i = open + 1;
+    if bytes.get(i) == Some(&b'!') {
+        i += 1;
+    }
+    if bytes.get(i) == Some(&b']') {
+        i += 1;
+    }
+    bytes[i.min(bytes.len())..]
+        .iter()
+        .position(|&b| b == b']')
+        .map_or(open, |end| i + end)
+}
+
+/// Lists the files matching any of `patterns`, each only once even if it matches several.
+/// Invalid patterns match nothing; [Source::check] reports them.
+fn globs(patterns: Vec<String>, options: glob::MatchOptions) -> Box<dyn Iterator<Item = PathBuf>> {
+    let mut seen = HashSet::new();
+    Box::new(
+        patterns
+            .into_iter()
+            .filter_map(move |pattern| glob::glob_with(&pattern, options).ok())
+            .flatten()
+            .flatten()
+            .filter(move |path| seen.insert(path.clone())),
+    )
+}
+
+/// Splits `pattern` at its first component containing a glob metacharacter, returning the
+/// literal directory before it and whether more than one component follows
+#[cfg(feature = "notify")]
+fn glob_root(pattern: &Path) -> (PathBuf, bool) {
+    let components: Vec<_> = pattern.components().collect();
+    let literal = components
+        .iter()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .count()
+        // The last component names the files themselves
+        .min(components.len().saturating_sub(1));
+
+    let root: PathBuf = components[..literal].iter().collect();
+    let root = if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    };
+
+    (root, components.len() - literal > 1)
+}
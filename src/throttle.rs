@@ -0,0 +1,56 @@
+use std::{
+    io::Read,
+    time::{Duration, Instant},
+};
+
+/// A token bucket that paces how many bytes are read per second
+pub(crate) struct RateLimiter {
+    bytes_per_sec: u64,
+
+    /// Bytes that can be read without waiting. Negative when reads have gotten ahead of the rate.
+    available: f64,
+
+    /// When `available` was last refilled
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        RateLimiter {
+            bytes_per_sec,
+            available: bytes_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Records that `bytes` were read, sleeping as long as needed to keep to the rate
+    pub(crate) fn consume(&mut self, bytes: u64) {
+        let rate = self.bytes_per_sec as f64;
+
+        // At most one second's worth of bytes can accumulate while idle
+        let refill = self.last_refill.elapsed().as_secs_f64() * rate;
+        self.available = (self.available + refill).min(rate) - bytes as f64;
+        self.last_refill = Instant::now();
+
+        if self.available < 0.0 {
+            std::thread::sleep(Duration::from_secs_f64(-self.available / rate));
+        }
+    }
+}
+
+/// Wraps a reader so that everything read through it is paced by a [RateLimiter]
+pub(crate) struct Throttled<'a, R> {
+    pub(crate) inner: R,
+    pub(crate) limiter: &'a mut RateLimiter,
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Keep individual reads small enough that pacing stays smooth
+        let max = buf.len().min(self.limiter.bytes_per_sec as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        self.limiter.consume(n as u64);
+        Ok(n)
+    }
+}
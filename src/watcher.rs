@@ -1,217 +1,3996 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+    },
     time::{Duration, Instant, SystemTime},
 };
 
-use crate::{FileResults, FileStatus, StopCondition};
+use crate::{
+    Aggregate, Callback, ChecksumAlgo, ConfigError, Counted, Detect, Discard, ErrorPolicy,
+    FileEvent, FileResults, FileStatus, Header, OnError, ProcessOrder, Reader, ResultSink, RetryIf,
+    SkipReason, StopCondition, StopContext, StopHandle, TickInfo, Ticked, Timeout, WatchConfig,
+    WatchError, WatchEvent, WithFile, WithMetadata, WithSink,
+};
+
+use crate::callback::Handler;
+use crate::checksum::add_digest;
+use crate::ignore::IgnoreFile;
+use crate::output::{log_error, log_info, log_warn};
+use crate::source::{expand_braces, Source};
+use crate::throttle::RateLimiter;
+
+#[cfg(feature = "regex")]
+use crate::Captures;
+#[cfg(feature = "memmap2")]
+use crate::Mapped;
+#[cfg(feature = "tokio")]
+use crate::{Async, AsyncCallback};
+
+type ResetHook = Box<dyn Fn(&Path, SystemTime)>;
+type YieldHook = Box<dyn FnMut()>;
+type StopFn = Box<dyn FnMut(&StopContext) -> bool>;
+type TickHook = Box<dyn FnMut(&TickInfo)>;
+type FileFilter = Box<dyn Fn(&Path) -> bool>;
+type Status<F> = FileStatus<<F as Handler>::Output, <F as Handler>::Error>;
+type ErrorClassifier = Box<dyn Fn(&std::io::Error) -> ErrorPolicy>;
+type DependencyFn = Box<dyn Fn(&Path) -> Vec<PathBuf>>;
+#[cfg(feature = "tokio")]
+type Tasks<T, E> = tokio::task::JoinSet<(Result<T, E>, Duration)>;
 
 pub struct Watcher<F> {
-    glob: String,
+    /// Where to look for files
+    source: Source,
 
     /// The closure to call when a file has matured
     callback: F,
 
+    /// How glob patterns are matched against paths
+    match_options: glob::MatchOptions,
+
+    /// If set, files matching the patterns in this file are ignored
+    ignore_file: Option<IgnoreFile>,
+
+    /// Files matching any of these patterns are ignored
+    excludes: Vec<glob::Pattern>,
+
+    /// If set, files for which this returns `false` are ignored
+    filter: Option<FileFilter>,
+
+    /// Files whose names end with any of these suffixes are ignored
+    ignored_suffixes: Vec<String>,
+
+    /// Whether files aren't processed while a sibling named with one of `ignored_suffixes` exists
+    wait_for_partials: bool,
+
     /// The duration between each check for new files.
     ///
     /// This globs files from the filesystem and compares them to files previously seen.
     check_interval: Duration,
 
+    /// If set, the minimum duration between looking for new files. Between these rescans, only
+    /// files already being tracked are checked.
+    rescan_interval: Option<Duration>,
+
+    /// If set, how long to keep checking for files to mature once watching is to stop
+    finalize_wait: Option<Duration>,
+
     /// Whether files should be deleted from disk after they're processed. Default is `false`.
     delete_on_completion: bool,
 
+    /// Patterns for file names, and whether matching files should be deleted after they're
+    /// processed. The first matching rule applies.
+    delete_rules: Vec<(glob::Pattern, bool)>,
+
+    /// If set, processed files are moved into this directory
+    archive_dir: Option<PathBuf>,
+
+    /// If set, processed files are deleted this long after being processed rather than immediately
+    delete_after: Option<Duration>,
+
+    /// Whether deleted files are checked to no longer exist
+    verify_consumed: bool,
+
+    /// Whether files that were deleted or moved away after processing are no longer tracked
+    forget_consumed: bool,
+
     /// How long after a file is no longer updated until we consider it to be completed
     mature_after: Duration,
 
-    ///
+    /// If set, files are only considered completed once their size hasn't changed for this long
+    stable_size: Option<Duration>,
+
+    /// If set, files are only considered completed once they're at least this many bytes
+    min_size: Option<u64>,
+
+    /// If set, files are considered completed once they've been tracked this long, even if they're
+    /// still changing
+    max_wait: Option<Duration>,
+
+    /// Whether a file modified in the future matures relative to when it was seen
+    clamp_future_mtimes: bool,
+
+    /// The number of times a file is attempted before its error is recorded
+    max_attempts: u32,
+
+    /// How long to wait after a failed attempt before retrying a file
+    retry_backoff: Duration,
+
+    /// The number of matured files required before any of them are processed
+    min_batch: usize,
+
+    /// The order in which files that matured during the same check are processed
+    order: ProcessOrder,
+
+    /// If set, files are only considered completed once they end with these bytes
+    required_trailer: Option<Vec<u8>>,
+
+    /// If set, files are only considered completed once their size matches the one recorded in
+    /// a sidecar file with this suffix
+    size_sidecar: Option<String>,
+
+    /// Whether files are only considered completed once they can be locked exclusively
+    exclusive_access: bool,
+
+    /// If set, an empty marker file with this suffix is written next to each processed file
+    completion_marker: Option<String>,
+
+    /// If set, an empty marker file with this suffix is written next to each errored file
+    failure_marker: Option<String>,
+
+    /// If set, files last modified between these local times of day aren't considered completed
+    #[cfg(feature = "chrono")]
+    blackout: Option<(chrono::NaiveTime, chrono::NaiveTime)>,
+
+    /// Whether filesystem notifications determine when to look for new files
+    #[cfg(feature = "notify")]
+    use_notify: bool,
+
+    /// Whether to print progress information to stdout/stderr
     verbose: bool,
+
+    /// Whether matured files are only reported rather than processed
+    dry_run: bool,
+
+    /// If set, a warning is printed once this many files have been processed
+    warn_after_files: Option<usize>,
+
+    /// If set, a warning is printed once the watcher has run this long
+    warn_after_duration: Option<Duration>,
+
+    /// If set, the most files that can be waiting to mature at once
+    max_tracked_files: Option<usize>,
+
+    /// Whether a processed file is processed again once it's modified
+    reprocess_on_change: bool,
+
+    /// Whether files are identified by their inode in addition to their path
+    track_by_inode: bool,
+
+    /// Whether files that already exist when watching starts are ignored until they're modified
+    only_new: bool,
+
+    /// Whether matured files are processed as soon as they're found rather than after each scan
+    streaming: bool,
+
+    /// Whether processed files are only completed once they're deleted externally
+    require_ack: bool,
+
+    /// Whether matured files are atomically renamed to a private name before processing
+    claim: bool,
+
+    /// Whether a panicking callback is recorded as an error rather than unwinding
+    catch_panics: bool,
+
+    /// If set, successfully processed files are hashed with this algorithm
+    hash_algo: Option<ChecksumAlgo>,
+
+    /// If set, the contents of all successfully processed files are combined into one digest
+    /// with this algorithm
+    combined_algo: Option<ChecksumAlgo>,
+
+    /// If set, files whose contents match one of this many recently processed files are skipped
+    dedup_capacity: Option<usize>,
+
+    /// If set, files larger than this fraction of available memory are skipped
+    ram_fraction: Option<f64>,
+
+    /// If set, watching stops at the next check once this handle is signaled
+    cancel: Option<StopHandle>,
+
+    /// If set, watching stops at the next check once this returns `true`
+    stop_when: Option<StopFn>,
+
+    /// Called whenever a tracked file shrinks or its modification time goes backward
+    on_reset: Option<ResetHook>,
+
+    /// If set, lists the files that each file depends on, which must be processed before it
+    dependencies: Option<DependencyFn>,
+
+    /// Decides how errors reading a file's metadata are handled. If unset, the file is retried.
+    metadata_error_policy: Option<ErrorClassifier>,
+
+    /// Called after each file is processed, giving the caller a chance to do other work
+    yield_between_files: Option<YieldHook>,
+
+    /// Called at the end of each check with the watcher's progress so far
+    on_tick: Option<TickHook>,
+
+    /// If set, receives an event for each thing the watcher does
+    events: Option<Sender<WatchEvent>>,
 }
 
 impl<F, T, E> Watcher<F>
 where
-    F: Fn(&Path) -> Result<T, E>,
+    F: FnMut(&Path) -> Result<T, E>,
 {
+    /// Creates a watcher for files matching the glob pattern `glob`, which calls `callback` for
+    /// each file once it has matured.
+    ///
+    /// As in a shell, `{a,b}` in the pattern matches either alternative, so `images/*.{png,jpg}`
+    /// watches both PNG and JPEG files. Alternatives may contain further braces. Use `[{]` and
+    /// `[}]` to match literal braces; braces without a comma between them are also literal.
+    ///
+    /// A pattern that's invalid, including any of the patterns it expands to, matches no files.
+    /// [try_watch](Self::try_watch) reports it instead.
     pub fn new<U: ToString>(glob: U, callback: F) -> Self {
-        Watcher {
-            glob: glob.to_string(),
-            callback,
-            check_interval: Duration::from_secs(1),
-            delete_on_completion: false,
-            mature_after: Duration::from_secs(5),
-            verbose: false,
-        }
+        Watcher::with_callback(Source::glob(&glob.to_string()), callback)
     }
 
-    /// Sets the minimum [Duration] used for checking for new files to be processed or
-    /// existing files that haven't yet been completed.
+    /// Creates a watcher for the single file at `path` that calls `callback` every time the file
+    /// changes and then matures again, such as to reload a configuration file. Unlike other
+    /// watchers, the file is processed again after each modification rather than only once;
+    /// [FileResults::completed] holds the result of the most recent change.
+    pub fn watch_file<P: Into<PathBuf>>(path: P, callback: F) -> Self {
+        let pattern = glob::Pattern::escape(&path.into().to_string_lossy());
+        let mut watcher = Watcher::with_callback(Source::Glob(vec![pattern]), callback);
+        watcher.reprocess_on_change = true;
+        watcher
+    }
+
+    /// Creates a watcher for files directly within `dir` whose names match `regex`, for patterns
+    /// that globs can't express, such as `^\d{8}_final\.csv$`. Note that the regex is searched for
+    /// within the file name; anchor it with `^` and `$` to match the whole name.
     ///
-    /// Note that this is the _minimum_ duration; due to processing time for other files,
-    /// the actual time may exceed this.
-    pub fn check_duration(mut self, duration: Duration) -> Self {
-        self.check_interval = duration;
-        self
+    /// To also receive the regex's capture groups, use `new_captures`.
+    #[cfg(feature = "regex")]
+    pub fn new_regex<P: Into<PathBuf>>(dir: P, regex: regex::Regex, callback: F) -> Self {
+        let source = Source::Regex {
+            dir: dir.into(),
+            regex,
+        };
+        Watcher::with_callback(source, callback)
     }
 
-    pub fn delete_on_completion(mut self, delete: bool) -> Self {
-        self.delete_on_completion = delete;
-        self
+    /// Creates a watcher for every file directly within `root`, or with
+    /// [recursive](Self::recursive) or [max_depth](Self::max_depth), within its subdirectories
+    /// too. Directories themselves are never passed to `callback`. Use [filter](Self::filter) or
+    /// [exclude](Self::exclude) to narrow down which files are processed.
+    #[cfg(feature = "walkdir")]
+    pub fn new_dir<P: Into<PathBuf>>(root: P, callback: F) -> Self {
+        let source = Source::Dir {
+            root: root.into(),
+            max_depth: Some(1),
+        };
+        Watcher::with_callback(source, callback)
     }
 
-    pub fn verbose(mut self, verbose: bool) -> Self {
-        self.verbose = verbose;
-        self
+    /// Creates a watcher with all settings taken from `config`, which is validated first.
+    pub fn from_config<U: ToString>(
+        glob: U,
+        config: WatchConfig,
+        callback: F,
+    ) -> Result<Self, ConfigError> {
+        Watcher::new(glob, callback).config(config)
     }
+}
 
-    pub fn maturation(mut self, duration: Duration) -> Self {
-        self.mature_after = duration;
+impl<G, T, E> Watcher<Counted<G>>
+where
+    G: FnMut(&Path) -> Result<(T, u64), E>,
+{
+    /// Creates a watcher whose callback also returns the number of logical records (eg, rows or
+    /// messages) each file produced. These count toward [StopCondition::RecordsFound] and
+    /// [FileResults::total_records]; each file's result is reported along with its count.
+    pub fn new_counted<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(Source::glob(&glob.to_string()), Counted(callback))
+    }
+}
+
+impl<G, T, E> Watcher<Ticked<G>>
+where
+    G: FnMut(&Path, u64) -> Result<T, E>,
+{
+    /// Creates a watcher whose callback also receives the number of the check (counting from 1)
+    /// during which each file was processed, which helps correlate callback output with the
+    /// watcher's own [verbose](Self::verbose) output.
+    pub fn new_ticked<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(Source::glob(&glob.to_string()), Ticked(callback))
+    }
+}
+
+impl<G, T, E> Watcher<WithMetadata<G>>
+where
+    G: FnMut(&FileEvent) -> Result<T, E>,
+{
+    /// Creates a watcher whose callback receives a [FileEvent] for each matured file, which
+    /// includes the file's size and modification time so the callback needn't read its metadata
+    /// again.
+    pub fn with_metadata_callback<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(Source::glob(&glob.to_string()), WithMetadata(callback))
+    }
+}
+
+impl<G, T, E> Watcher<WithFile<G>>
+where
+    G: FnMut(PathBuf, File) -> Result<T, E>,
+{
+    /// Creates a watcher whose callback receives each matured file already opened for reading,
+    /// along with its path. This avoids the file being deleted or replaced between maturing and
+    /// the callback opening it. Files that can't be opened are reported in
+    /// [FileResults::skipped] as [SkipReason::OpenFailed] rather than passed to the callback.
+    pub fn with_file_callback<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(
+            Source::glob(&glob.to_string()),
+            WithFile {
+                callback,
+                open_error: None,
+            },
+        )
+    }
+}
+
+impl<G, T, E> Watcher<Reader<G>>
+where
+    G: FnMut(&Path, &mut dyn Read) -> Result<T, E>,
+{
+    /// Creates a watcher whose callback receives a reader over each matured file's contents
+    /// rather than just its path.
+    pub fn new_reader<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(Source::glob(&glob.to_string()), Reader::new(callback))
+    }
+}
+
+impl<G, T, E> Watcher<Header<G>>
+where
+    G: FnMut(&Path, &[u8]) -> Result<T, E>,
+{
+    /// Creates a watcher whose callback receives at most the first `len` bytes of each matured
+    /// file, such as to classify files by their magic bytes or header row without reading them in
+    /// full. Files shorter than `len` are passed in their entirety.
+    pub fn new_header<U: ToString>(glob: U, len: usize, callback: G) -> Self {
+        Watcher::with_callback(
+            Source::glob(&glob.to_string()),
+            Header {
+                callback,
+                len,
+                rate_limit: None,
+            },
+        )
+    }
+}
+
+impl<G, T, E> Watcher<Aggregate<G>>
+where
+    G: FnMut(&Path, &mut dyn Write) -> Result<T, E>,
+{
+    /// Creates a watcher whose callback receives a writer in addition to each matured file's path.
+    /// All files share the same output, which is stdout unless
+    /// [aggregate_output](Self::aggregate_output) is set.
+    ///
+    /// Writes from one file's callback are completed and flushed before the next file is
+    /// processed, so output from different files is never interleaved.
+    pub fn new_writer<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(Source::glob(&glob.to_string()), Aggregate::new(callback))
+    }
+}
+
+impl<G> Watcher<Aggregate<G>> {
+    /// Appends all output to the file at `path`, creating it if needed. Make sure `path` doesn't
+    /// match the watcher's own pattern.
+    pub fn aggregate_output<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.callback.output = Some(path.into());
         self
     }
+}
+
+#[cfg(feature = "memmap2")]
+impl<G, T, E> Watcher<Mapped<G>>
+where
+    G: FnMut(&Path, &[u8]) -> Result<T, E>,
+{
+    /// Creates a watcher whose callback receives the contents of each matured file via a
+    /// memory map, which avoids reading very large files into memory. Empty files are passed
+    /// as an empty slice.
+    pub fn new_mmap<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(
+            Source::glob(&glob.to_string()),
+            Mapped {
+                callback,
+                rate_limit: None,
+            },
+        )
+    }
+}
 
-    pub fn watch(&mut self, condition: StopCondition) -> FileResults<T, E>
-    where
-        E: From<std::io::Error>,
-    {
-        let mut files_seen = HashMap::<PathBuf, FileStatus<T, E>>::new();
+#[cfg(feature = "regex")]
+impl<G, T, E> Watcher<Captures<G>>
+where
+    G: FnMut(&Path, HashMap<String, String>) -> Result<T, E>,
+{
+    /// Creates a watcher for files directly within `dir` whose names match `regex`. Files whose
+    /// names don't match are ignored.
+    ///
+    /// The callback receives the regex's named capture groups as extracted from the file name, so
+    /// metadata encoded in names like `orders_2024-01-15_region-west.csv` needn't be re-parsed.
+    /// Note that the regex is searched for within the file name; anchor it with `^` and `$` to
+    /// match the whole name.
+    pub fn new_captures<P: Into<PathBuf>>(dir: P, regex: regex::Regex, callback: G) -> Self {
+        let source = Source::Regex {
+            dir: dir.into(),
+            regex: regex.clone(),
+        };
+        Watcher::with_callback(source, Captures { regex, callback })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<G, Fut, T, E> Watcher<Async<G>>
+where
+    G: FnMut(PathBuf) -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+{
+    /// Creates a watcher whose callback returns a future, for processing files with async I/O.
+    /// Such a watcher is run by [watch_async](Self::watch_async) rather than the blocking watch
+    /// methods.
+    ///
+    /// Each future is spawned onto the Tokio runtime, so it must be `Send` and own what it uses,
+    /// such as the path it's given.
+    pub fn new_async<U: ToString>(glob: U, callback: G) -> Self {
+        Watcher::with_callback(
+            Source::glob(&glob.to_string()),
+            Async {
+                callback,
+                concurrency: 1,
+            },
+        )
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<G> Watcher<Async<G>>
+where
+    G: AsyncCallback,
+    G::Future: Send + 'static,
+    G::Output: Send + 'static,
+    G::Error: From<std::io::Error> + Send + 'static,
+{
+    /// Processes up to `limit` matured files at once. Defaults to 1, processing files one at a
+    /// time in the order they matured.
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.callback.concurrency = limit.max(1);
+        self
+    }
 
-        let start_time = Instant::now();
-        let mut newest_file = SystemTime::now();
+    /// Watches for files until `condition` is met like [watch](Self::watch), but waits between
+    /// checks with `tokio::time::sleep` and runs the callback's futures concurrently, up to
+    /// [concurrency](Self::concurrency) at a time. Must be called from within a Tokio runtime.
+    ///
+    /// Looking for files and checking their metadata still uses blocking filesystem calls. The
+    /// returned future isn't `Send`, since the watcher's hooks aren't, so await it directly
+    /// (eg, from `#[tokio::main]`) rather than spawning it. A file that
+    /// [depends](Self::dependency_fn) on another that matured during the same check waits for
+    /// the next check.
+    pub async fn watch_async(
+        &mut self,
+        condition: StopCondition,
+    ) -> FileResults<G::Output, G::Error> {
+        let mut session = self.start();
+        session.deferred = Some(Vec::new());
 
         loop {
-            // Check all files
-            let iteration_start = Instant::now();
+            if let Some(pause) = self.until_next_check(&session) {
+                self.pause_async(pause).await;
+            }
 
-            for file in glob::glob(&self.glob)
-                .expect("Couldn't glob files")
-                .flatten()
-            {
-                match modification_time(&file) {
-                    Err(e) => {
-                        // Couldn't get metadata->modified time, so we can't track it.
-                        if self.verbose {
-                            eprintln!("Couldn't get metadata for {}: {e:?}", file.display());
-                        }
+            self.check(&mut session);
+            self.process_deferred(&mut session).await;
+            if self.finish_check(&mut session, &condition) {
+                break;
+            }
+        }
 
-                        files_seen.insert(file, FileStatus::Error(e.into()));
-                    }
-                    Ok(current_systime) => {
-                        let entry = files_seen
-                            .entry(file.clone())
-                            .or_insert_with(|| FileStatus::Processing(current_systime));
-
-                        if let FileStatus::Processing(last_seen) = entry {
-                            // The file was previously seen; update its last seen time (which may or may not be
-                            // different than what was previously set).
-                            newest_file = current_systime;
-                            *last_seen = current_systime;
-
-                            // This file hasn't yet been processed
-                            if let Ok(d) = last_seen.elapsed() {
-                                // Able to calculate the Duration from the Systemtime
-                                if d >= self.mature_after {
-                                    // The last modified date is old enough for us to consider this file completed.
-                                    *entry = match (self.callback)(&file) {
-                                        Ok(t) if self.delete_on_completion => {
-                                            match (std::fs::remove_file(&file), self.verbose) {
-                                                (Ok(_), true) => println!(
-                                                    "Processed and deleted {}.",
-                                                    file.display()
-                                                ),
-                                                (Err(e), true) => {
-                                                    eprintln!(
-                                                        "Processed but failed to delete {}: {e:?}",
-                                                        file.display()
-                                                    )
-                                                }
-                                                _ => {}
-                                            }
-                                            FileStatus::ProcessingCompleted(t)
-                                        }
-                                        Ok(t) => FileStatus::ProcessingCompleted(t),
-                                        Err(e) => FileStatus::Error(e),
-                                    };
-                                }
-                            }
-                        }
-                    }
-                }
+        if let Some(deadline) = self.finalize_deadline(&session) {
+            while let Some(pause) = self.finalize_pause(&session, deadline) {
+                tokio::time::sleep(pause).await;
+                self.scan(&mut session);
+                self.process_deferred(&mut session).await;
             }
+        }
+        self.release_held(&mut session);
+        self.process_deferred(&mut session).await;
 
-            match condition {
-                StopCondition::Once => break,
-                StopCondition::FilesFound(n) => {
-                    if files_seen
-                        .values()
-                        .filter(|f| matches!(f, FileStatus::ProcessingCompleted(_)))
-                        .count()
-                        >= n
-                    {
-                        if self.verbose {
-                            println!(
-                                "Processing halted: {n} files have been successfully processed."
-                            )
-                        }
+        session.into_results()
+    }
 
-                        break;
-                    }
-                }
-                StopCondition::Elapsed(d) => {
-                    if d > start_time.elapsed() {
-                        if self.verbose {
-                            println!("Processing halted: {d:?} elapsed since processing started.");
-                        }
-                        break;
-                    }
-                }
-                StopCondition::NoNewFilesSince(d) => {
-                    if let Ok(newest) = newest_file.elapsed() {
-                        if newest >= d {
-                            if self.verbose {
-                                println!(
-                                "Processing halted: {d:?} elapsed since a new file has been seen."
-                            );
-                            }
+    /// Same as [pause](Self::pause), but without blocking the runtime
+    async fn pause_async(&self, duration: Duration) {
+        let Some(cancel) = &self.cancel else {
+            tokio::time::sleep(duration).await;
+            return;
+        };
 
-                            break;
-                        }
-                    }
-                }
+        let deadline = Instant::now() + duration;
+        while !cancel.is_stopped() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
             }
+            tokio::time::sleep(remaining.min(CANCEL_POLL_INTERVAL)).await;
+        }
+    }
 
-            let iteration_elapsed = iteration_start.elapsed();
+    /// Processes the files that matured during the last check, running up to
+    /// [concurrency](Self::concurrency) of the callback's futures at once
+    async fn process_deferred(&mut self, session: &mut Session<G::Output, G::Error>) {
+        let files = match &mut session.deferred {
+            Some(deferred) => std::mem::take(deferred),
+            None => return,
+        };
 
-            if self.check_interval > iteration_elapsed {
-                std::thread::sleep(self.check_interval - iteration_elapsed);
+        let mut tasks = tokio::task::JoinSet::new();
+        let mut running = HashMap::new();
+        for file in files {
+            while tasks.len() >= self.callback.concurrency {
+                self.join_next(&mut tasks, &mut running, session).await;
             }
+
+            let prepared = {
+                #[cfg(feature = "tracing")]
+                let _entered = session.span(&file).entered();
+                self.prepare(&file, session)
+            };
+            let prepared = match prepared {
+                Ok(prepared) => prepared,
+                Err(status) => {
+                    self.record(file, status, session);
+                    continue;
+                }
+            };
+
+            let future = self.callback.callback.call(prepared.event.path.clone());
+            let future = async move {
+                let start = Instant::now();
+                let result = future.await;
+                (result, start.elapsed())
+            };
+            #[cfg(feature = "tracing")]
+            let future = tracing::Instrument::instrument(future, session.span(&file));
+
+            let id = tasks.spawn(future).id();
+            running.insert(id, (file, prepared));
         }
 
-        let mut completed = HashMap::new();
-        let mut not_processed = Vec::new();
-        let mut errored = HashMap::new();
+        while !tasks.is_empty() {
+            self.join_next(&mut tasks, &mut running, session).await;
+        }
+    }
 
-        for (path, status) in files_seen {
-            match status {
-                FileStatus::ProcessingCompleted(t) => {
-                    completed.insert(path, t);
-                }
-                FileStatus::Processing(_) => not_processed.push(path),
-                FileStatus::Error(e) => {
-                    errored.insert(path, e);
+    /// Waits for one of the running callbacks to finish and records its file's result
+    async fn join_next(
+        &mut self,
+        tasks: &mut Tasks<G::Output, G::Error>,
+        running: &mut HashMap<tokio::task::Id, (PathBuf, Prepared)>,
+        session: &mut Session<G::Output, G::Error>,
+    ) {
+        let (id, result, elapsed) = match tasks.join_next_with_id().await {
+            Some(Ok((id, (result, elapsed)))) => (id, result, elapsed),
+            Some(Err(e)) if e.is_panic() => {
+                let id = e.id();
+                let payload = e.into_panic();
+                if !self.catch_panics {
+                    std::panic::resume_unwind(payload);
                 }
+                (id, Err(panic_error(payload)), Duration::ZERO)
             }
-        }
+            // Tasks are never aborted
+            Some(Err(e)) => panic!("callback task failed: {e}"),
+            None => return,
+        };
 
-        FileResults {
-            completed,
-            not_processed,
-            errored,
+        let Some((file, prepared)) = running.remove(&id) else {
+            return;
+        };
+
+        #[cfg(feature = "tracing")]
+        let _entered = session.span(&file).entered();
+        let status = self.complete(&file, prepared, result, elapsed, session);
+        self.record(file, Some(status), session);
+
+        if let Some(yield_between_files) = &mut self.yield_between_files {
+            yield_between_files();
         }
     }
 }
 
-/// Result flattening [is unstable](https://github.com/rust-lang/rust/issues/70142),
-/// so this function simplifies getting the system time from a file
-fn modification_time(path: &Path) -> Result<SystemTime, std::io::Error> {
-    let metadata = path.metadata()?;
-    let modified = metadata.modified()?;
-    Ok(modified)
+impl Watcher<Detect> {
+    /// Creates a watcher with no callback, for when only knowing which files have matured is
+    /// needed. See [watch_notify](Self::watch_notify).
+    pub fn detect<U: ToString>(glob: U) -> Self {
+        Watcher::with_callback(Source::glob(&glob.to_string()), Detect)
+    }
+
+    /// Watches for files until `condition` is met, yielding each file's path as soon as it has
+    /// matured. This is the underlying "detect stable files" primitive, for bringing your own
+    /// processing.
+    ///
+    /// Files are not deleted unless [delete_on_completion](Self::delete_on_completion) is set.
+    pub fn watch_notify(mut self, condition: StopCondition) -> impl Iterator<Item = PathBuf> {
+        let mut session = self.start();
+        session.recent = Some(Vec::new());
+
+        let mut matured = VecDeque::new();
+        let mut finished = false;
+
+        std::iter::from_fn(move || loop {
+            if let Some(path) = matured.pop_front() {
+                return Some(path);
+            }
+
+            if finished {
+                return None;
+            }
+
+            finished = self.step(&mut session, &condition);
+            matured.extend(
+                session
+                    .recent
+                    .as_mut()
+                    .map(std::mem::take)
+                    .unwrap_or_default(),
+            );
+        })
+    }
+}
+
+impl<G> Watcher<Reader<G>> {
+    /// Treats files whose names end with `suffix` (eg, `.manifest`) as manifests for files that
+    /// were uploaded in parts.
+    ///
+    /// A manifest such as `file.dat.manifest` lists the names of its parts, one per line and in
+    /// order, relative to the manifest's directory. Once the manifest matures, all parts must
+    /// exist; they are concatenated and passed to the callback as a single reader under the
+    /// logical path `file.dat`. If [delete_on_completion](Self::delete_on_completion) is set, the
+    /// parts are deleted along with the manifest. Results are reported under the manifest's path.
+    ///
+    /// The pattern should match only the manifests, not the parts themselves, which would
+    /// otherwise also be processed individually.
+    pub fn assemble<U: ToString>(mut self, suffix: U) -> Self {
+        self.callback.manifest_suffix = Some(suffix.to_string());
+        self
+    }
+}
+
+impl<G> Watcher<Reader<G>> {
+    /// Limits how quickly this watcher reads files to about `bytes_per_sec`, such as to avoid
+    /// saturating a shared network mount. Reads are paced as the callback consumes each file.
+    pub fn read_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.callback.rate_limit = Some(RateLimiter::new(bytes_per_sec));
+        self
+    }
+}
+
+impl<G> Watcher<Header<G>> {
+    /// Limits how quickly this watcher reads files to about `bytes_per_sec`, such as to avoid
+    /// saturating a shared network mount.
+    pub fn read_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.callback.rate_limit = Some(RateLimiter::new(bytes_per_sec));
+        self
+    }
+}
+
+#[cfg(feature = "memmap2")]
+impl<G> Watcher<Mapped<G>> {
+    /// Limits how quickly this watcher consumes files to about `bytes_per_sec`, such as to avoid
+    /// saturating a shared network mount. Because a memory map is read lazily, each file's full
+    /// size is counted against the limit before the callback is called, which paces files rather
+    /// than individual reads.
+    pub fn read_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.callback.rate_limit = Some(RateLimiter::new(bytes_per_sec));
+        self
+    }
+}
+
+#[cfg(feature = "flate2")]
+impl<G> Watcher<Reader<G>> {
+    /// Sets whether files with a `.gz` extension are transparently decompressed before being
+    /// passed to the callback. Other files are passed through as-is. Default is `false`.
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.callback.decompress = decompress;
+        self
+    }
+}
+
+/// Shows the watcher's source and [settings](Watcher::to_config), but not its callback or hooks
+impl<F> std::fmt::Debug for Watcher<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watcher")
+            .field("source", &self.source)
+            .field("callback", &format_args!("<callback>"))
+            .field("config", &self.to_config())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> Watcher<F> {
+    fn with_callback(source: Source, callback: F) -> Self {
+        Watcher {
+            source,
+            callback,
+            match_options: glob::MatchOptions::new(),
+            ignore_file: None,
+            excludes: Vec::new(),
+            filter: None,
+            ignored_suffixes: Vec::new(),
+            wait_for_partials: false,
+            check_interval: Duration::from_secs(1),
+            rescan_interval: None,
+            finalize_wait: None,
+            delete_on_completion: false,
+            delete_rules: Vec::new(),
+            archive_dir: None,
+            delete_after: None,
+            verify_consumed: false,
+            forget_consumed: false,
+            mature_after: Duration::from_secs(5),
+            stable_size: None,
+            min_size: None,
+            max_wait: None,
+            clamp_future_mtimes: false,
+            max_attempts: 1,
+            retry_backoff: Duration::ZERO,
+            min_batch: 1,
+            order: ProcessOrder::Fifo,
+            required_trailer: None,
+            size_sidecar: None,
+            exclusive_access: false,
+            completion_marker: None,
+            failure_marker: None,
+            #[cfg(feature = "chrono")]
+            blackout: None,
+            #[cfg(feature = "notify")]
+            use_notify: false,
+            verbose: false,
+            dry_run: false,
+            warn_after_files: None,
+            warn_after_duration: None,
+            max_tracked_files: None,
+            claim: false,
+            catch_panics: false,
+            streaming: false,
+            require_ack: false,
+            reprocess_on_change: false,
+            track_by_inode: false,
+            only_new: false,
+            hash_algo: None,
+            combined_algo: None,
+            dedup_capacity: None,
+            ram_fraction: None,
+            cancel: None,
+            stop_when: None,
+            on_reset: None,
+            metadata_error_policy: None,
+            dependencies: None,
+            yield_between_files: None,
+            on_tick: None,
+            events: None,
+        }
+    }
+
+    /// Replaces the callback, keeping all other settings
+    fn map_callback<G>(self, map: impl FnOnce(F) -> G) -> Watcher<G> {
+        Watcher {
+            source: self.source,
+            callback: map(self.callback),
+            match_options: self.match_options,
+            ignore_file: self.ignore_file,
+            excludes: self.excludes,
+            filter: self.filter,
+            ignored_suffixes: self.ignored_suffixes,
+            wait_for_partials: self.wait_for_partials,
+            check_interval: self.check_interval,
+            rescan_interval: self.rescan_interval,
+            finalize_wait: self.finalize_wait,
+            delete_on_completion: self.delete_on_completion,
+            delete_rules: self.delete_rules,
+            archive_dir: self.archive_dir,
+            delete_after: self.delete_after,
+            verify_consumed: self.verify_consumed,
+            forget_consumed: self.forget_consumed,
+            mature_after: self.mature_after,
+            stable_size: self.stable_size,
+            min_size: self.min_size,
+            max_wait: self.max_wait,
+            clamp_future_mtimes: self.clamp_future_mtimes,
+            max_attempts: self.max_attempts,
+            retry_backoff: self.retry_backoff,
+            min_batch: self.min_batch,
+            order: self.order,
+            required_trailer: self.required_trailer,
+            size_sidecar: self.size_sidecar,
+            exclusive_access: self.exclusive_access,
+            completion_marker: self.completion_marker,
+            failure_marker: self.failure_marker,
+            #[cfg(feature = "chrono")]
+            blackout: self.blackout,
+            #[cfg(feature = "notify")]
+            use_notify: self.use_notify,
+            verbose: self.verbose,
+            dry_run: self.dry_run,
+            warn_after_files: self.warn_after_files,
+            warn_after_duration: self.warn_after_duration,
+            max_tracked_files: self.max_tracked_files,
+            streaming: self.streaming,
+            require_ack: self.require_ack,
+            reprocess_on_change: self.reprocess_on_change,
+            track_by_inode: self.track_by_inode,
+            only_new: self.only_new,
+            claim: self.claim,
+            catch_panics: self.catch_panics,
+            hash_algo: self.hash_algo,
+            combined_algo: self.combined_algo,
+            dedup_capacity: self.dedup_capacity,
+            ram_fraction: self.ram_fraction,
+            cancel: self.cancel,
+            stop_when: self.stop_when,
+            on_reset: self.on_reset,
+            metadata_error_policy: self.metadata_error_policy,
+            dependencies: self.dependencies,
+            yield_between_files: self.yield_between_files,
+            on_tick: self.on_tick,
+            events: self.events,
+        }
+    }
+
+    /// Applies the watcher's glob pattern relative to each of `bases` rather than the current
+    /// directory. For example, with bases `site-a` and `site-b`, the pattern `incoming/*.csv`
+    /// matches `site-a/incoming/*.csv` and `site-b/incoming/*.csv`. Use
+    /// [FileResults::completed_under] to group results by the base they were found under.
+    ///
+    /// This has no effect on watchers that don't use a glob pattern, such as
+    /// `new_captures`.
+    pub fn base_dirs(mut self, bases: Vec<PathBuf>) -> Self {
+        self.source = match self.source {
+            Source::Glob(patterns) | Source::Bases { patterns, .. } => {
+                Source::Bases { bases, patterns }
+            }
+            #[cfg(feature = "regex")]
+            source @ Source::Regex { .. } => source,
+            #[cfg(feature = "walkdir")]
+            source @ Source::Dir { .. } => source,
+        };
+        self
+    }
+
+    /// Also watches files matching `pattern`, in addition to the watcher's original pattern. Files
+    /// from every pattern are processed by the same callback and reported together, and a file
+    /// matching more than one pattern is only processed once. Like the original pattern, `pattern`
+    /// is applied relative to any [base directories](Self::base_dirs) and may contain
+    /// [braces](Self::new).
+    ///
+    /// This has no effect on watchers that don't use a glob pattern, such as
+    /// `new_captures`.
+    pub fn add_glob<U: ToString>(mut self, pattern: U) -> Self {
+        match &mut self.source {
+            Source::Glob(patterns) | Source::Bases { patterns, .. } => {
+                patterns.extend(expand_braces(&pattern.to_string()))
+            }
+            #[cfg(feature = "regex")]
+            Source::Regex { .. } => {}
+            #[cfg(feature = "walkdir")]
+            Source::Dir { .. } => {}
+        }
+        self
+    }
+
+    /// Sets whether a watcher created with `new_dir` also watches files in subdirectories of its
+    /// root, at any depth, including subdirectories created while watching. Default is `false`,
+    /// which only watches files directly within the root.
+    ///
+    /// This has no effect on other watchers.
+    #[cfg(feature = "walkdir")]
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        if let Source::Dir { max_depth, .. } = &mut self.source {
+            *max_depth = if recursive { None } else { Some(1) };
+        }
+        self
+    }
+
+    /// Limits a watcher created with `new_dir` to files at most `depth` levels below its root,
+    /// which implies [recursive](Self::recursive). Files directly within the root are at depth 1.
+    ///
+    /// This has no effect on other watchers.
+    #[cfg(feature = "walkdir")]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        if let Source::Dir { max_depth, .. } = &mut self.source {
+            *max_depth = Some(depth);
+        }
+        self
+    }
+
+    /// Sets the options used when matching the glob pattern against paths, such as case
+    /// sensitivity and whether wildcards match a leading `.`, exactly as with
+    /// [glob::glob_with]. By default, [glob::MatchOptions::new] is used.
+    pub fn match_options(mut self, options: glob::MatchOptions) -> Self {
+        self.match_options = options;
+        self
+    }
+
+    /// Ignores files matching the patterns listed in the gitignore-style file at `path` (eg,
+    /// `.watchignore`), so the set of exclusions can be maintained alongside the data. Patterns
+    /// are relative to the ignore file's directory, and the file is reloaded whenever it changes.
+    /// The ignore file itself is always ignored.
+    ///
+    /// A subset of `.gitignore` syntax is supported: `#` comments, `!` to negate a pattern, a
+    /// trailing `/` to match only directories, and patterns containing `/` that match relative
+    /// paths rather than names. As with `.gitignore`, the last matching pattern wins.
+    ///
+    /// Ignored files are recorded in [FileResults::skipped] as [SkipReason::Ignored], so they can
+    /// be told apart from files that never matched. A file that's no longer ignored once the
+    /// ignore file changes is tracked as though it were new.
+    pub fn ignore_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ignore_file = Some(IgnoreFile::new(path.into()));
+        self
+    }
+
+    /// Ignores files whose paths match `pattern` (eg, `*.tmp.csv` or `*.swp`), even though they
+    /// match the watched pattern. This can be called multiple times to exclude several patterns.
+    /// Patterns are matched using the [match options](Self::match_options).
+    ///
+    /// Panics if `pattern` is invalid.
+    pub fn exclude<U: ToString>(mut self, pattern: U) -> Self {
+        let pattern = glob::Pattern::new(&pattern.to_string()).expect("Invalid exclude pattern");
+        self.excludes.push(pattern);
+        self
+    }
+
+    /// Ignores files for which `predicate` returns `false`, for criteria that patterns can't
+    /// express, such as a date in the file name. Rejected files aren't tracked or reported in
+    /// [FileResults]. This applies in addition to any [excluded](Self::exclude) patterns, and is
+    /// called for each matching file at every check.
+    pub fn filter<G>(mut self, predicate: G) -> Self
+    where
+        G: Fn(&Path) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(predicate));
+        self
+    }
+
+    /// Ignores files whose names end with any of `suffixes` (eg, `.part`, `.crdownload` or
+    /// `.tmp`), as written by browsers and tools like rsync before they rename a complete file to
+    /// its final name. This can be called multiple times to ignore more suffixes.
+    pub fn ignore_extensions<U: ToString>(mut self, suffixes: &[U]) -> Self {
+        self.ignored_suffixes
+            .extend(suffixes.iter().map(ToString::to_string));
+        self
+    }
+
+    /// Sets whether a file is only processed once no sibling with its name plus one of the
+    /// [ignored suffixes](Self::ignore_extensions) exists, eg, `report.pdf` isn't processed while
+    /// `report.pdf.crdownload` is still there. This is for downloaders that create the final file
+    /// before they've finished with its partial counterpart, regardless of how long either has
+    /// gone unmodified.
+    pub fn wait_for_partials(mut self, wait: bool) -> Self {
+        self.wait_for_partials = wait;
+        self
+    }
+
+    /// Applies every setting in `config`, replacing any previously set values. Returns an error
+    /// if `config` isn't [valid](WatchConfig::validate).
+    pub fn config(mut self, config: WatchConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+
+        self.check_interval = config.check_interval;
+        self.rescan_interval = config.rescan_interval;
+        self.excludes = config
+            .exclude
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern).expect("Invalid exclude pattern"))
+            .collect();
+        self.ignored_suffixes = config.ignore_extensions;
+        self.wait_for_partials = config.wait_for_partials;
+        self.mature_after = config.mature_after;
+        self.stable_size = config.mature_on_stable_size;
+        self.min_size = config.min_size;
+        self.max_wait = config.max_wait;
+        self.clamp_future_mtimes = config.clamp_future_mtimes;
+        self.finalize_wait = config.finalize_wait;
+        self.delete_on_completion = config.delete_on_completion;
+        self.delete_rules = compile_rules(config.delete_rules);
+        self.archive_dir = config.move_on_completion;
+        self.delete_after = config.delete_after;
+        self.verify_consumed = config.verify_consumed;
+        self.forget_consumed = config.forget_consumed;
+        self.claim = config.claim;
+        self.catch_panics = config.catch_panics;
+        self.streaming = config.streaming;
+        self.track_by_inode = config.track_by_inode;
+        self.only_new = config.only_new;
+        self.reprocess_on_change = config.reprocess_on_change;
+        self.max_attempts = config.max_attempts;
+        self.retry_backoff = config.retry_backoff;
+        self.min_batch = config.min_batch;
+        self.order = config.order;
+        self.required_trailer = config.require_trailing;
+        self.size_sidecar = config.require_size_sidecar;
+        self.exclusive_access = config.require_exclusive_access;
+        self.completion_marker = config.write_completion_marker;
+        self.failure_marker = config.write_failure_marker;
+        self.hash_algo = config.hash_completed;
+        self.combined_algo = config.combined_digest;
+        self.dedup_capacity = config.dedup_lru;
+        self.ram_fraction = config.max_size_fraction_of_ram;
+        self.warn_after_files = config.warn_after_files;
+        self.warn_after_duration = config.warn_after_duration;
+        self.max_tracked_files = config.max_tracked_files;
+        self.verbose = config.verbose;
+        self.dry_run = config.dry_run;
+        Ok(self)
+    }
+
+    /// The watcher's current data-only settings, as would be applied by [config](Self::config).
+    /// This is useful for checking the result of a chain of builder methods, or for saving a
+    /// watcher's configuration.
+    pub fn to_config(&self) -> WatchConfig {
+        WatchConfig {
+            check_interval: self.check_interval,
+            rescan_interval: self.rescan_interval,
+            exclude: self
+                .excludes
+                .iter()
+                .map(|pattern| pattern.as_str().to_string())
+                .collect(),
+            ignore_extensions: self.ignored_suffixes.clone(),
+            wait_for_partials: self.wait_for_partials,
+            mature_after: self.mature_after,
+            mature_on_stable_size: self.stable_size,
+            min_size: self.min_size,
+            max_wait: self.max_wait,
+            clamp_future_mtimes: self.clamp_future_mtimes,
+            finalize_wait: self.finalize_wait,
+            delete_on_completion: self.delete_on_completion,
+            delete_rules: self
+                .delete_rules
+                .iter()
+                .map(|(pattern, delete)| (pattern.as_str().to_string(), *delete))
+                .collect(),
+            move_on_completion: self.archive_dir.clone(),
+            delete_after: self.delete_after,
+            verify_consumed: self.verify_consumed,
+            forget_consumed: self.forget_consumed,
+            claim: self.claim,
+            catch_panics: self.catch_panics,
+            streaming: self.streaming,
+            track_by_inode: self.track_by_inode,
+            only_new: self.only_new,
+            reprocess_on_change: self.reprocess_on_change,
+            max_attempts: self.max_attempts,
+            retry_backoff: self.retry_backoff,
+            min_batch: self.min_batch,
+            order: self.order,
+            require_trailing: self.required_trailer.clone(),
+            require_size_sidecar: self.size_sidecar.clone(),
+            require_exclusive_access: self.exclusive_access,
+            write_completion_marker: self.completion_marker.clone(),
+            write_failure_marker: self.failure_marker.clone(),
+            hash_completed: self.hash_algo,
+            dedup_lru: self.dedup_capacity,
+            combined_digest: self.combined_algo,
+            max_size_fraction_of_ram: self.ram_fraction,
+            warn_after_files: self.warn_after_files,
+            warn_after_duration: self.warn_after_duration,
+            max_tracked_files: self.max_tracked_files,
+            verbose: self.verbose,
+            dry_run: self.dry_run,
+        }
+    }
+
+    /// Sets the minimum [Duration] used for checking for new files to be processed or
+    /// existing files that haven't yet been completed.
+    ///
+    /// Note that this is the _minimum_ duration; due to processing time for other files,
+    /// the actual time may exceed this.
+    pub fn check_duration(mut self, duration: Duration) -> Self {
+        self.check_interval = duration;
+        self
+    }
+
+    /// Sets the minimum [Duration] between full scans for new files. By default, every check
+    /// looks for new files.
+    ///
+    /// Discovering new files (eg, globbing a huge directory) is usually much more expensive than
+    /// re-checking the modification times of files already being tracked. With a rescan interval
+    /// longer than the [check duration](Self::check_duration), checks in between rescans only
+    /// look at known files that haven't yet matured.
+    pub fn rescan_interval(mut self, interval: Duration) -> Self {
+        self.rescan_interval = Some(interval);
+        self
+    }
+
+    /// Sets whether to look for new files only when filesystem notifications (inotify, FSEvents,
+    /// or ReadDirectoryChangesW) report a change in the watched directories, rather than at every
+    /// check. Default is `false`.
+    ///
+    /// Files already being tracked are still checked at every check, so maturation works the same
+    /// way, as do the results. If notifications aren't available for the source's directories (eg,
+    /// one doesn't exist yet), the watcher falls back to looking for new files at every check. A
+    /// [rescan interval](Self::rescan_interval) additionally forces a periodic scan, as a
+    /// safeguard against missed notifications.
+    #[cfg(feature = "notify")]
+    pub fn use_notify(mut self, use_notify: bool) -> Self {
+        self.use_notify = use_notify;
+        self
+    }
+
+    /// Once the [StopCondition] is met (or the watcher is cancelled), keeps checking for up to
+    /// `wait` so that files which have nearly matured get a final chance to be processed. Waiting
+    /// ends early once no files are left to mature. Files that still haven't matured are reported in
+    /// [FileResults::not_processed] as usual.
+    pub fn finalize_wait(mut self, wait: Duration) -> Self {
+        self.finalize_wait = Some(wait);
+        self
+    }
+
+    pub fn delete_on_completion(mut self, delete: bool) -> Self {
+        self.delete_on_completion = delete;
+        self
+    }
+
+    /// Decides whether each successfully processed file is deleted based on its name. Each rule is
+    /// a glob pattern (eg, `*.tmp`) and whether files matching it should be deleted; the first
+    /// rule matching a file's name applies. Files matching no rule are deleted according to
+    /// [delete_on_completion](Self::delete_on_completion) and [delete_after](Self::delete_after).
+    ///
+    /// Panics if any pattern is invalid.
+    pub fn delete_rules(mut self, rules: Vec<(String, bool)>) -> Self {
+        self.delete_rules = compile_rules(rules);
+        self
+    }
+
+    /// Moves each successfully processed file into `dir`, keeping its file name, rather than
+    /// leaving it in place. This takes precedence over [delete_on_completion](Self::delete_on_completion).
+    ///
+    /// `dir` is created if it doesn't exist. If a file of the same name is already there, a number
+    /// is added before the extension so that it isn't overwritten (eg, `data.1.csv`). Files are
+    /// renamed, so `dir` should be on the same filesystem as the watched files.
+    pub fn move_on_completion<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.archive_dir = Some(dir.into());
+        self
+    }
+
+    /// Deletes each successfully processed file once `retention` has passed since it was
+    /// processed, rather than immediately. Combined with [move_on_completion](Self::move_on_completion),
+    /// files are archived right away and deleted from the archive later.
+    ///
+    /// Expired files are deleted at each check, so only files whose retention period passes
+    /// while the watcher is still running are deleted.
+    pub fn delete_after(mut self, retention: Duration) -> Self {
+        self.delete_after = Some(retention);
+        self
+    }
+
+    /// Sets whether a processed file is checked to be gone after it's deleted. Default is `false`.
+    ///
+    /// On unreliable storage, a deletion can appear to succeed while the file remains, or a
+    /// producer may recreate the file right away. When a file still exists after being deleted,
+    /// it's recorded in [FileResults::cleanup_failures] rather than [FileResults::deleted].
+    pub fn verify_consumed(mut self, verify: bool) -> Self {
+        self.verify_consumed = verify;
+        self
+    }
+
+    /// Sets whether files are forgotten once they've been processed and then deleted or moved
+    /// away, such as by [delete_on_completion](Self::delete_on_completion). Default is `false`.
+    ///
+    /// Normally, every file's result is kept until watching stops so that it can be returned in
+    /// [FileResults], which means memory grows with each file processed. For a watcher that runs
+    /// indefinitely, forgetting consumed files keeps memory bounded by the number of files on disk
+    /// instead. The tradeoff is that forgotten files appear nowhere in [FileResults] except
+    /// [forgotten](FileResults::forgotten), which counts those that succeeded; they still count
+    /// toward [StopCondition::FilesFound]. Use [result_sink](Self::result_sink) or
+    /// [watch_iter](Self::watch_iter) to handle each result as it's produced.
+    ///
+    /// Files that remain where they were, including those that failed, are still tracked so that
+    /// they aren't processed again. A file that [depends](Self::dependency_fn) on a forgotten file
+    /// will wait for it indefinitely.
+    pub fn forget_consumed(mut self, forget: bool) -> Self {
+        self.forget_consumed = forget;
+        self
+    }
+
+    /// Sets whether the watcher describes what it's doing, such as each file it processes or
+    /// deletes and any it fails to. Messages are printed to stdout and stderr, or with the `log`
+    /// feature, sent through the `log` crate at the info, warn and error levels. Default is
+    /// `false`.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sets whether to only report which files would be processed, for checking patterns and
+    /// maturation settings against live data. Default is `false`.
+    ///
+    /// Files are found and matured as usual, but each matured file is recorded in
+    /// [FileResults::would_process] instead of being passed to the callback. Nothing that would
+    /// follow processing happens, so files aren't claimed, deleted, moved, hashed or marked. For
+    /// [stop conditions](StopCondition), these files count as processed successfully.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Prints a warning to stderr once more than `count` files have been successfully processed,
+    /// without stopping. This is a guardrail for catching an unexpected flood of files, separate
+    /// from the [StopCondition]. The warning is printed even if [verbose](Self::verbose) isn't set.
+    pub fn warn_after_files(mut self, count: usize) -> Self {
+        self.warn_after_files = Some(count);
+        self
+    }
+
+    /// Prints a warning to stderr once the watcher has been running longer than `duration`,
+    /// without stopping. Like [warn_after_files](Self::warn_after_files), the warning is printed
+    /// even if [verbose](Self::verbose) isn't set.
+    pub fn warn_after_duration(mut self, duration: Duration) -> Self {
+        self.warn_after_duration = Some(duration);
+        self
+    }
+
+    /// Limits how many files can be waiting to mature at once, guarding against a flood of files
+    /// from a buggy or malicious producer exhausting memory. Once the limit is reached, newly found
+    /// files are left untracked until the existing backlog has been processed; they're picked up
+    /// again by a later check. A warning is printed to stderr when the limit is reached, and not
+    /// again until the backlog has dropped below it.
+    pub fn max_tracked_files(mut self, count: usize) -> Self {
+        self.max_tracked_files = Some(count);
+        self
+    }
+
+    pub fn maturation(mut self, duration: Duration) -> Self {
+        self.mature_after = duration;
+        self
+    }
+
+    /// Requires that a file's size hasn't changed for at least `duration` before it is considered
+    /// completed, as observed at each check. This is for storage such as network shares where
+    /// modification times may not be updated while a file is still being appended to. It applies
+    /// in addition to the [maturation](Self::maturation) period, which can be set to
+    /// [Duration::ZERO] to rely on the size alone.
+    pub fn mature_on_stable_size(mut self, duration: Duration) -> Self {
+        self.stable_size = Some(duration);
+        self
+    }
+
+    /// Requires that a file be at least `bytes` long before it is considered completed, such as
+    /// to avoid processing an empty placeholder created before a download's content arrives.
+    /// Smaller files keep waiting to mature. This applies in addition to the
+    /// [maturation](Self::maturation) period.
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = Some(bytes);
+        self
+    }
+
+    /// Considers a file completed once it has been tracked for `duration`, even if it's still
+    /// being modified, so that a file that is touched continually is still processed. Combined
+    /// with the [maturation](Self::maturation) period, a file is processed once it's been idle for
+    /// that long, but never waits longer than `duration`.
+    ///
+    /// This overrides the maturation period and [stable size](Self::mature_on_stable_size), but
+    /// not requirements on a file's contents, such as [min_size](Self::min_size).
+    pub fn max_wait(mut self, duration: Duration) -> Self {
+        self.max_wait = Some(duration);
+        self
+    }
+
+    /// Sets how a file whose modification time is in the future, such as from clock skew on a
+    /// network mount, matures. Default is `false`.
+    ///
+    /// By default, such a file is left waiting until the system clock passes its modification
+    /// time and the [maturation](Self::maturation) period has then elapsed, which may be never if
+    /// the skew is large. When `true`, the maturation period is instead measured from when the
+    /// watcher first saw the file with that modification time.
+    pub fn clamp_future_mtimes(mut self, clamp: bool) -> Self {
+        self.clamp_future_mtimes = clamp;
+        self
+    }
+
+    /// Sets how many times the callback is called for a file before its error is recorded in
+    /// [FileResults::errored]. A failed file is attempted again at the next check, or once the
+    /// [backoff](Self::retry) has passed. By default, every error is retried; use
+    /// [retry_if](Self::retry_if) to only retry some of them. Default is `1`, which never retries.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Retries a file whose callback fails, such as while a downstream service is briefly
+    /// unavailable, up to a total of `max_attempts` attempts. Each retry happens at the first
+    /// check at least `backoff` after the failed attempt. Once every attempt has failed, the last
+    /// error is recorded in [FileResults::errored]. [FileResults::attempts] reports how many
+    /// attempts each file took.
+    ///
+    /// A file still waiting to be retried when watching stops is reported in
+    /// [FileResults::not_processed].
+    pub fn retry(mut self, max_attempts: u32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Withholds processing until at least `count` files have matured, then processes all of them.
+    /// Files still go to the callback one at a time; this only controls when processing starts,
+    /// which is useful when downstream work is more efficient in groups.
+    ///
+    /// When watching stops, any matured files that were being held are processed even if there
+    /// are fewer than `count`. Default is `1`.
+    pub fn min_batch(mut self, count: usize) -> Self {
+        self.min_batch = count;
+        self
+    }
+
+    /// Sets the order in which files that matured during the same check are processed. Default is
+    /// [ProcessOrder::Fifo], the order in which they were first seen.
+    ///
+    /// Ordering is best-effort: it only applies within a check, so a file that matures at a later
+    /// check is processed after those of earlier checks regardless of the order. It doesn't apply
+    /// when [streaming](Self::streaming), and [dependency_fn](Self::dependency_fn) takes
+    /// precedence over it.
+    pub fn order(mut self, order: ProcessOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Requires that a file end with the specified bytes (eg, `b"\n"` or a format's trailing
+    /// magic bytes) before it is considered completed. This check is made in addition to the
+    /// [maturation](Self::maturation) period.
+    ///
+    /// Files that never end with `trailer` are never processed and will be reported in
+    /// [FileResults::not_processed].
+    pub fn require_trailing(mut self, trailer: Vec<u8>) -> Self {
+        self.required_trailer = Some(trailer);
+        self
+    }
+
+    /// Requires that a file's size match the byte count recorded in its sidecar file before it is
+    /// considered completed. The sidecar for `data.bin` with a `suffix` of `.size` is
+    /// `data.bin.size`, which should contain only the expected size as a decimal number. This check
+    /// is made in addition to the [maturation](Self::maturation) period.
+    ///
+    /// Files whose sidecar is missing or can't be parsed aren't processed until it can be. Sidecars
+    /// are left in place after processing, so the pattern should match only the data files.
+    pub fn require_size_sidecar<U: ToString>(mut self, suffix: U) -> Self {
+        self.size_sidecar = Some(suffix.to_string());
+        self
+    }
+
+    /// Requires that a file can be locked exclusively before it is considered completed, so that
+    /// files still locked by their writer aren't processed even if they've gone unmodified for
+    /// the [maturation](Self::maturation) period. Files that can't be locked are checked again at
+    /// the next check.
+    ///
+    /// Locks are taken with `flock` on Unix and `LockFileEx` on Windows, on a read-only handle, so
+    /// only writers that lock the file themselves are detected. Elsewhere, files are never
+    /// considered locked.
+    pub fn require_exclusive_access(mut self, require: bool) -> Self {
+        self.exclusive_access = require;
+        self
+    }
+
+    /// Writes an empty marker file after each file is processed successfully, for downstream
+    /// consumers that poll for markers rather than watching files. The marker for `data.csv` with
+    /// a `suffix` of `.done` is `data.csv.done`. A [failure marker](Self::write_failure_marker)
+    /// left by an earlier attempt is removed.
+    ///
+    /// Markers are written even if the file itself is deleted or moved, so the pattern should
+    /// match only the data files.
+    pub fn write_completion_marker<U: ToString>(mut self, suffix: U) -> Self {
+        self.completion_marker = Some(suffix.to_string());
+        self
+    }
+
+    /// Writes an empty marker file once a file has failed and won't be retried, like
+    /// [write_completion_marker](Self::write_completion_marker) does for successes. A completion
+    /// marker left by an earlier run is removed.
+    pub fn write_failure_marker<U: ToString>(mut self, suffix: U) -> Self {
+        self.failure_marker = Some(suffix.to_string());
+        self
+    }
+
+    /// Ignores files whose modification time falls within a daily window, from `start` up to
+    /// `end` in local time, such as while a nightly maintenance job rewrites them. Such files
+    /// aren't considered completed until they're modified again outside of the window. If `end` is
+    /// before `start`, the window spans midnight.
+    #[cfg(feature = "chrono")]
+    pub fn blackout(mut self, start: chrono::NaiveTime, end: chrono::NaiveTime) -> Self {
+        self.blackout = Some((start, end));
+        self
+    }
+
+    /// Sets whether a matured file is claimed before it's processed. Default is `false`.
+    ///
+    /// Claiming atomically renames the file to a name private to this watcher, so when several
+    /// watchers (or overlapping patterns) match the same file, only one of them processes it;
+    /// the others skip it. This holds for watchers in the same process, such as those in a
+    /// [WatcherGroup](crate::WatcherGroup), as well as in different processes. The callback
+    /// receives the claimed path, while results are still reported under the original path.
+    ///
+    /// Unless the file is deleted or moved after processing, it is renamed back afterward. A
+    /// watcher that saw the file being claimed doesn't process it again unless it's modified, but
+    /// one that didn't check for files while it was claimed (eg, because the callback was quicker
+    /// than its [check_interval](Self::check_duration)) will process it again. Processing each
+    /// file exactly once therefore requires
+    /// [delete_on_completion](Self::delete_on_completion) or
+    /// [move_on_completion](Self::move_on_completion).
+    ///
+    /// A claimed file is named `.claimed-<pid>-<n>-<name>`, next to where it was found. If the
+    /// process exits while a file is claimed, such as by crashing during the callback, the file
+    /// keeps that name, and since watchers skip claimed files, it won't be processed. Recover it
+    /// by renaming it back to `<name>`, such as before starting watchers again.
+    pub fn claim(mut self, claim: bool) -> Self {
+        self.claim = claim;
+        self
+    }
+
+    /// Sets whether a panic in the callback is caught and recorded in [FileResults::errored] as
+    /// an error naming the panic, rather than unwinding out of [watch](Self::watch) and losing the
+    /// results gathered so far. Watching continues with the next file. Default is `false`.
+    ///
+    /// The panic is still reported by the panic hook, which prints it to stderr by default.
+    pub fn catch_panics(mut self, catch: bool) -> Self {
+        self.catch_panics = catch;
+        self
+    }
+
+    /// Sets whether files are identified by their inode as well as their path. Default is `false`.
+    ///
+    /// This detects two cases that paths alone can't. A processed file that is atomically replaced
+    /// by renaming a new file over it keeps its path but gets a new inode, so it's processed again.
+    /// A processed file that is renamed keeps its inode, so it isn't processed again under its new
+    /// path; instead, the new path is reported in [FileResults::skipped] as [SkipReason::Renamed].
+    ///
+    /// Inodes are only available on Unix; elsewhere, this has no effect.
+    pub fn track_by_inode(mut self, track: bool) -> Self {
+        self.track_by_inode = track;
+        self
+    }
+
+    /// Sets whether files that already exist when watching starts are ignored, so that only files
+    /// created afterward are processed. Default is `false`.
+    ///
+    /// A file that existed at the start but is modified afterward counts as new and is processed.
+    pub fn only_new(mut self, only_new: bool) -> Self {
+        self.only_new = only_new;
+        self
+    }
+
+    /// Sets whether a file that was already processed is processed again once it's modified and
+    /// has matured again, such as a log or configuration file that's rewritten repeatedly.
+    /// Default is `false`, which processes each file only once. This is always set by
+    /// [watch_file](Self::watch_file).
+    ///
+    /// The file's entry in [FileResults] is replaced by the result of its latest processing. A
+    /// file that failed is also tried again once it's modified. Files that were deleted or moved
+    /// after processing are gone, so they aren't processed again unless they're recreated.
+    pub fn reprocess_on_change(mut self, reprocess: bool) -> Self {
+        self.reprocess_on_change = reprocess;
+        self
+    }
+
+    /// Sets whether each matured file is processed as soon as the scan finds it, rather than
+    /// collecting all matured files and processing them once the scan completes. Default is
+    /// `false`.
+    ///
+    /// For directories with enormous numbers of matches, this avoids holding a list of every
+    /// matured path in memory at once. The tradeoff is that features which need the whole set of
+    /// matured files don't apply: [min_batch](Self::min_batch) is ignored, and
+    /// [dependency_fn](Self::dependency_fn) can only hold a file back until its dependencies have
+    /// been processed rather than ordering the files within a scan.
+    pub fn streaming(mut self, streaming: bool) -> Self {
+        self.streaming = streaming;
+        self
+    }
+
+    /// Sets whether a successfully processed file is only considered completed once it has been
+    /// deleted by someone else, such as a downstream consumer acknowledging that it has durably
+    /// stored the result. Default is `false`.
+    ///
+    /// Until then, the file continues to be tracked. If it's modified again before being deleted,
+    /// it's treated as a new file that must mature and be processed again. Files still waiting
+    /// when watching stops are reported in [FileResults::awaiting_ack].
+    ///
+    /// Since the consumer is responsible for deleting files, they aren't deleted or moved by the
+    /// watcher in this mode.
+    pub fn require_ack(mut self, ack: bool) -> Self {
+        self.require_ack = ack;
+        self
+    }
+
+    /// Computes a digest of each successfully processed file's contents, which is reported in
+    /// [FileResults::hashes]. This provides a record of exactly what was processed.
+    ///
+    /// The file is read after the callback returns but before it is deleted.
+    pub fn hash_completed(mut self, algo: ChecksumAlgo) -> Self {
+        self.hash_algo = Some(algo);
+        self
+    }
+
+    /// Combines digests of every successfully processed file's contents into a single digest,
+    /// reported in [FileResults::combined_hash]. This is a cheap fingerprint for checking whether
+    /// two runs processed the same set of files.
+    ///
+    /// Per-file digests are summed, so the result doesn't depend on the order files were
+    /// processed in. Files are read after the callback returns but before they are deleted.
+    pub fn combined_digest(mut self, algo: ChecksumAlgo) -> Self {
+        self.combined_algo = Some(algo);
+        self
+    }
+
+    /// Skips files whose contents are identical to one of the `capacity` most recently processed
+    /// files, recording them in [FileResults::skipped] as [SkipReason::Duplicate]. Files are
+    /// hashed before they're processed to check for duplicates.
+    ///
+    /// This is a best-effort way to catch repeated deliveries of the same file using bounded
+    /// memory: once more than `capacity` distinct files have been processed, the oldest are
+    /// forgotten, and a duplicate of one of them will be processed again.
+    pub fn dedup_lru(mut self, capacity: usize) -> Self {
+        self.dedup_capacity = Some(capacity);
+        self
+    }
+
+    /// Skips files larger than `fraction` of the memory available when watching starts, recording
+    /// them in [FileResults::skipped] as [SkipReason::TooLargeForMemory]. This protects callbacks
+    /// that read whole files into memory from unexpectedly huge inputs.
+    ///
+    /// Available memory is currently only detected on Linux; elsewhere, no limit is applied.
+    pub fn max_size_fraction_of_ram(mut self, fraction: f64) -> Self {
+        self.ram_fraction = Some(fraction);
+        self
+    }
+
+    /// Stops watching at the next check once `handle` is signaled from another thread,
+    /// regardless of the [StopCondition] passed to [watch](Self::watch). The results accumulated
+    /// so far are returned as usual.
+    ///
+    /// `handle` may be a [StopHandle] or an `Arc<AtomicBool>`, such as one set by a signal
+    /// handler. Cancellation is noticed promptly even with a long
+    /// [check_interval](Self::check_duration); files already being processed are finished first,
+    /// and [finalize_wait](Self::finalize_wait), if set, still applies.
+    pub fn with_cancel<H: Into<StopHandle>>(mut self, handle: H) -> Self {
+        self.cancel = Some(handle.into());
+        self
+    }
+
+    /// Stops watching once `predicate` returns `true`, in addition to the [StopCondition] passed
+    /// to [watch](Self::watch). It's called after each check with the watcher's current progress,
+    /// for conditions the built-in ones don't cover, such as a sentinel file appearing:
+    ///
+    /// ```no_run
+    /// # use watch_files::{StopCondition, Watcher};
+    /// # use std::path::Path;
+    /// let results = Watcher::new("*.txt", |path| std::fs::read_to_string(path))
+    ///     .stop_when(|_| Path::new("STOP").exists())
+    ///     .watch(StopCondition::Elapsed(std::time::Duration::MAX));
+    /// ```
+    pub fn stop_when<G>(mut self, predicate: G) -> Self
+    where
+        G: FnMut(&StopContext) -> bool + 'static,
+    {
+        self.stop_when = Some(Box::new(predicate));
+        self
+    }
+
+    /// Sets a callback that is invoked each time a file that is waiting to mature appears to have
+    /// been rewritten rather than appended to: its size shrank, or its modification time went
+    /// backward. Like any other modification, this restarts its maturation period.
+    ///
+    /// This is useful for diagnosing files that never seem to get processed because a producer
+    /// keeps truncating or replacing them. Ordinary writes, such as appends, don't invoke it.
+    pub fn on_reset<G>(mut self, callback: G) -> Self
+    where
+        G: Fn(&Path, SystemTime) + 'static,
+    {
+        self.on_reset = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets how errors reading a file's metadata (eg, to check its modification time) are
+    /// handled. `policy` is called with each error and decides whether to retry the file at the
+    /// next check, skip it permanently, or stop watching altogether. For example, a vanished file
+    /// (`NotFound`) or a transient `WouldBlock` can be retried while `PermissionDenied` is
+    /// skipped.
+    ///
+    /// By default, every such error is retried, so that files which are briefly inaccessible
+    /// (eg, while their permissions are set after creation) are still processed. A file being
+    /// retried that's no longer found when the source is next scanned is dropped rather than
+    /// reported as an error.
+    pub fn metadata_error_policy<G>(mut self, policy: G) -> Self
+    where
+        G: Fn(&std::io::Error) -> ErrorPolicy + 'static,
+    {
+        self.metadata_error_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Sets a function that lists the files each file depends on (eg, as read from a sidecar
+    /// file), so that a file is only processed once all of its dependencies have been
+    /// successfully processed during this run. Matured files are processed in dependency order;
+    /// a file whose dependencies haven't yet completed keeps waiting.
+    ///
+    /// Dependencies must be given as the same paths the watcher finds them under. Matured files
+    /// that are part of a dependency cycle, or that depend on one, are reported in
+    /// [FileResults::errored].
+    pub fn dependency_fn<G>(mut self, dependencies: G) -> Self
+    where
+        G: Fn(&Path) -> Vec<PathBuf> + 'static,
+    {
+        self.dependencies = Some(Box::new(dependencies));
+        self
+    }
+
+    /// Sends a [WatchEvent] to `sender` as each file is found, matures, is processed, and is
+    /// deleted, giving a structured feed for logging, progress bars, or metrics. Events are sent
+    /// regardless of [verbose](Self::verbose), and are no longer sent once the receiver has been
+    /// dropped.
+    pub fn events(mut self, sender: Sender<WatchEvent>) -> Self {
+        self.events = Some(sender);
+        self
+    }
+
+    /// Sets a callback that is invoked after each file is processed.
+    ///
+    /// This doesn't change how files are processed, but it allows a host that runs the watcher
+    /// on its own thread (eg, a GUI or cooperative event loop) to pump its own work, check a
+    /// flag, or sleep between files rather than being blocked for an entire scan.
+    pub fn yield_between_files<G>(mut self, callback: G) -> Self
+    where
+        G: FnMut() + 'static,
+    {
+        self.yield_between_files = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets a callback that is invoked at the end of each check, whether or not any file changed,
+    /// with counts of the files seen, pending, completed and errored so far.
+    ///
+    /// Unlike [events](Self::events), which reports each file's transitions, this is a periodic
+    /// heartbeat, convenient for driving a status line or progress bar. It's called by both
+    /// [watch](Self::watch) and `watch_async`, as well as while iterating with
+    /// [watch_iter](Self::watch_iter).
+    pub fn on_tick<G>(mut self, callback: G) -> Self
+    where
+        G: FnMut(&TickInfo) + 'static,
+    {
+        self.on_tick = Some(Box::new(callback));
+        self
+    }
+
+    /// A one-line summary of the effective configuration, used for verbose output
+    fn describe(&self) -> String {
+        #[allow(unused_mut)]
+        let mut description = format!(
+            "source={:?} match_options={:?} ignore_file={:?} exclude={:?} filter={} ignore_extensions={:?} wait_for_partials={} \
+             mature_after={:?} mature_on_stable_size={:?} min_size={:?} max_wait={:?} clamp_future_mtimes={} check_interval={:?} rescan_interval={:?} \
+             finalize_wait={:?} delete_on_completion={} delete_rules={:?} move_on_completion={:?} delete_after={:?} verify_consumed={} forget_consumed={} claim={} catch_panics={} streaming={} require_ack={} \
+             track_by_inode={} only_new={} reprocess_on_change={} \
+             max_attempts={} retry_backoff={:?} min_batch={} order={:?} require_trailing={:?} require_size_sidecar={:?} require_exclusive_access={} \
+             write_completion_marker={:?} write_failure_marker={:?} hash_completed={:?} \
+             combined_digest={:?} dedup_lru={:?} \
+             max_size_fraction_of_ram={:?} warn_after_files={:?} \
+             warn_after_duration={:?} max_tracked_files={:?} cancellable={} stop_when={} on_reset={} metadata_error_policy={} dependency_fn={} \
+             yield_between_files={} on_tick={} events={} dry_run={}",
+            self.source,
+            self.match_options,
+            self.ignore_file.as_ref().map(IgnoreFile::path),
+            self.excludes
+                .iter()
+                .map(glob::Pattern::as_str)
+                .collect::<Vec<_>>(),
+            self.filter.is_some(),
+            self.ignored_suffixes,
+            self.wait_for_partials,
+            self.mature_after,
+            self.stable_size,
+            self.min_size,
+            self.max_wait,
+            self.clamp_future_mtimes,
+            self.check_interval,
+            self.rescan_interval,
+            self.finalize_wait,
+            self.delete_on_completion,
+            self.delete_rules
+                .iter()
+                .map(|(pattern, delete)| (pattern.as_str(), delete))
+                .collect::<Vec<_>>(),
+            self.archive_dir,
+            self.delete_after,
+            self.verify_consumed,
+            self.forget_consumed,
+            self.claim,
+            self.catch_panics,
+            self.streaming,
+            self.require_ack,
+            self.track_by_inode,
+            self.only_new,
+            self.reprocess_on_change,
+            self.max_attempts,
+            self.retry_backoff,
+            self.min_batch,
+            self.order,
+            self.required_trailer,
+            self.size_sidecar,
+            self.exclusive_access,
+            self.completion_marker,
+            self.failure_marker,
+            self.hash_algo,
+            self.combined_algo,
+            self.dedup_capacity,
+            self.ram_fraction,
+            self.warn_after_files,
+            self.warn_after_duration,
+            self.max_tracked_files,
+            self.cancel.is_some(),
+            self.stop_when.is_some(),
+            self.on_reset.is_some(),
+            self.metadata_error_policy.is_some(),
+            self.dependencies.is_some(),
+            self.yield_between_files.is_some(),
+            self.on_tick.is_some(),
+            self.events.is_some(),
+            self.dry_run,
+        );
+
+        #[cfg(feature = "chrono")]
+        if let Some((start, end)) = self.blackout {
+            description += &format!(" blackout={start}-{end}");
+        }
+
+        #[cfg(feature = "notify")]
+        {
+            description += &format!(" use_notify={}", self.use_notify);
+        }
+
+        description
+    }
+
+    /// Sends `event` to the [events](Self::events) channel, if any
+    fn emit(&self, event: WatchEvent) {
+        if let Some(sender) = &self.events {
+            // A dropped receiver just means nobody is listening anymore
+            let _ = sender.send(event);
+        }
+    }
+}
+
+/// Yields each file's result as soon as it's final.
+///
+/// Created by [Watcher::watch_iter].
+pub struct WatchIter<'a, F: Callback> {
+    watcher: &'a mut Watcher<F>,
+    session: Session<F::Output, F::Error>,
+    condition: StopCondition,
+    done: bool,
+}
+
+impl<F> Iterator for WatchIter<'_, F>
+where
+    F: Callback,
+    F::Error: From<std::io::Error>,
+{
+    type Item = (PathBuf, Result<F::Output, F::Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(finished) = self.session.take_finished() {
+                return Some(finished);
+            }
+            if self.done {
+                return None;
+            }
+            self.done = self.watcher.step(&mut self.session, &self.condition);
+        }
+    }
+}
+
+/// A matured file that is ready to be passed to the callback
+struct Prepared {
+    /// Where the file currently is, which differs from its path if it was claimed
+    target: PathBuf,
+
+    event: FileEvent,
+
+    /// The file's digest, if [Watcher::dedup_lru] was set
+    digest: Option<String>,
+}
+
+/// A tracked file's modification time and size and when each was first observed, along with when
+/// the file itself was first observed
+struct Observation {
+    since: Instant,
+    modified: SystemTime,
+    modified_since: Instant,
+    size: u64,
+    size_since: Instant,
+}
+
+impl Observation {
+    fn new(modified: SystemTime, size: u64) -> Self {
+        let now = Instant::now();
+        Observation {
+            since: now,
+            modified,
+            modified_since: now,
+            size,
+            size_since: now,
+        }
+    }
+
+    /// Records the file's current metadata, noting when either value changes
+    fn update(&mut self, modified: SystemTime, size: u64) {
+        if self.modified != modified {
+            self.modified = modified;
+            self.modified_since = Instant::now();
+        }
+        if self.size != size {
+            self.size = size;
+            self.size_since = Instant::now();
+        }
+    }
+}
+
+/// State accumulated over the course of a single call to [Watcher::watch]
+struct Session<T, E> {
+    files_seen: HashMap<PathBuf, FileStatus<T, E>>,
+
+    /// Digests of successfully processed files, if [Watcher::hash_completed] was set
+    hashes: HashMap<PathBuf, String>,
+
+    /// The sum of digests of successfully processed files, if [Watcher::combined_digest] was set
+    combined_hash: Option<Vec<u8>>,
+
+    /// Digests of recently processed files and their paths, if [Watcher::dedup_lru] was set
+    recent_digests: HashMap<String, PathBuf>,
+
+    /// The keys of `recent_digests`, oldest first
+    digest_order: VecDeque<String>,
+
+    /// Files deleted after processing
+    deleted: Vec<PathBuf>,
+
+    /// Files that couldn't be deleted after processing
+    cleanup_failures: HashMap<PathBuf, std::io::Error>,
+
+    start_time: Instant,
+
+    /// Processed files awaiting deletion, and when they were processed, in that order
+    retained: VecDeque<(PathBuf, Instant)>,
+
+    /// Total time spent looking for files and checking their metadata
+    scan_time: Duration,
+
+    /// Total time spent in the callback
+    process_time: Duration,
+
+    /// When files were last checked
+    last_check: Option<Instant>,
+
+    /// The number of the current check, counting from 1
+    tick: u64,
+
+    /// Files successfully processed since this was last drained. Only tracked when iterating
+    /// over results as they happen.
+    recent: Option<Vec<PathBuf>>,
+
+    /// When the source was last fully scanned for new files
+    last_rescan: Option<Instant>,
+
+    /// Whether the source has matched any file at all
+    matched_any: bool,
+
+    /// Files larger than this many bytes are skipped, if [Watcher::max_size_fraction_of_ram] was set
+    memory_limit: Option<u64>,
+
+    /// When a file was last tracked for the first time, or when watching started
+    last_new_file: Instant,
+
+    /// The last observed metadata of each file waiting to mature, if
+    /// [Watcher::mature_on_stable_size], [Watcher::min_size], [Watcher::max_wait],
+    /// [Watcher::clamp_future_mtimes] or [Watcher::on_reset] was set
+    observed: HashMap<PathBuf, Observation>,
+
+    /// Change notifications for the source's directories, if [Watcher::use_notify] was set and
+    /// they're available
+    #[cfg(feature = "notify")]
+    events: Option<crate::events::Events>,
+
+    /// A span for each tracked file, from when it's first seen until its result is final
+    #[cfg(feature = "tracing")]
+    spans: HashMap<PathBuf, tracing::Span>,
+
+    /// Matured files waiting for [Watcher::watch_async] to process them concurrently
+    #[cfg(feature = "tokio")]
+    deferred: Option<Vec<PathBuf>>,
+
+    /// The number of records produced by successfully processed files
+    total_records: u64,
+
+    /// The number of times the callback has been called for each file
+    attempts: HashMap<PathBuf, u32>,
+
+    /// The order in which each tracked file was first seen, counting from 0
+    discovered: HashMap<PathBuf, u64>,
+
+    /// The discovery number of the next newly tracked file
+    next_discovery: u64,
+
+    /// The order in which each file's result became final, counting from 0
+    sequence: HashMap<PathBuf, u64>,
+
+    /// The sequence number of the next file whose result becomes final
+    next_sequence: u64,
+
+    /// When each file waiting to be retried may next be attempted
+    retry_at: HashMap<PathBuf, Instant>,
+
+    /// Files whose results were handed out by [Watcher::watch_iter], and whether each succeeded
+    yielded: HashMap<PathBuf, bool>,
+
+    /// The modification time of each processed file when it was processed, if
+    /// [Watcher::watch_file] reprocesses files on change
+    processed_mtimes: HashMap<PathBuf, SystemTime>,
+
+    /// Processed files that have since been deleted or moved away, waiting to be forgotten, if
+    /// [Watcher::forget_consumed] was set
+    consumed: Vec<PathBuf>,
+
+    /// The number of successfully processed files that have been forgotten
+    forgotten: usize,
+
+    /// The modification time of each file that existed when watching started, if
+    /// [Watcher::only_new] was set. Files are removed once they're modified.
+    preexisting: HashMap<PathBuf, SystemTime>,
+
+    /// The modification time of each file that another watcher claimed while this one was
+    /// tracking it, if [Watcher::claim] was set. Files are removed once they're modified.
+    claimed_elsewhere: HashMap<PathBuf, SystemTime>,
+
+    /// Distinguishes files claimed by this run from those claimed by other watchers in the same
+    /// process, if [Watcher::claim] was set
+    claimant: u64,
+
+    /// The latest metadata error of each file that's being retried, which is recorded in
+    /// [FileResults::errored] if the file is still failing when watching stops
+    unreadable: HashMap<PathBuf, E>,
+
+    /// Whether a metadata error's [ErrorPolicy] was to stop watching
+    aborted: bool,
+
+    /// Whether the [Watcher::warn_after_files] warning has been printed
+    warned_files: bool,
+
+    /// Whether the [Watcher::warn_after_duration] warning has been printed
+    warned_duration: bool,
+
+    /// Whether new files are being turned away because [Watcher::max_tracked_files] was reached,
+    /// so its warning has been printed
+    refusing: bool,
+
+    /// The device and inode of each tracked file, if [Watcher::track_by_inode] was set
+    file_ids: HashMap<PathBuf, FileId>,
+
+    /// The device and inode of each successfully processed file still on disk, and the path it was
+    /// processed under
+    processed_ids: HashMap<FileId, PathBuf>,
+}
+
+impl<T, E> Session<T, E> {
+    fn new(memory_limit: Option<u64>) -> Self {
+        Session {
+            files_seen: HashMap::new(),
+            hashes: HashMap::new(),
+            combined_hash: None,
+            recent_digests: HashMap::new(),
+            digest_order: VecDeque::new(),
+            deleted: Vec::new(),
+            cleanup_failures: HashMap::new(),
+            start_time: Instant::now(),
+            retained: VecDeque::new(),
+            scan_time: Duration::ZERO,
+            process_time: Duration::ZERO,
+            last_check: None,
+            tick: 0,
+            recent: None,
+            last_rescan: None,
+            matched_any: false,
+            memory_limit,
+            last_new_file: Instant::now(),
+            observed: HashMap::new(),
+            #[cfg(feature = "notify")]
+            events: None,
+            #[cfg(feature = "tracing")]
+            spans: HashMap::new(),
+            #[cfg(feature = "tokio")]
+            deferred: None,
+            total_records: 0,
+            attempts: HashMap::new(),
+            discovered: HashMap::new(),
+            next_discovery: 0,
+            sequence: HashMap::new(),
+            next_sequence: 0,
+            retry_at: HashMap::new(),
+            yielded: HashMap::new(),
+            processed_mtimes: HashMap::new(),
+            consumed: Vec::new(),
+            forgotten: 0,
+            preexisting: HashMap::new(),
+            claimed_elsewhere: HashMap::new(),
+            claimant: NEXT_CLAIMANT.fetch_add(1, Ordering::Relaxed),
+            unreadable: HashMap::new(),
+            aborted: false,
+            warned_files: false,
+            warned_duration: false,
+            refusing: false,
+            file_ids: HashMap::new(),
+            processed_ids: HashMap::new(),
+        }
+    }
+
+    fn stop_context(&self) -> StopContext {
+        StopContext {
+            completed: self
+                .files_seen
+                .values()
+                .filter(|f| {
+                    matches!(
+                        f,
+                        FileStatus::ProcessingCompleted(_) | FileStatus::WouldProcess
+                    )
+                })
+                .count()
+                + self
+                    .yielded
+                    .values()
+                    .filter(|succeeded| **succeeded)
+                    .count()
+                + self.forgotten,
+            records: self.total_records,
+            elapsed: self.start_time.elapsed(),
+            newest_file_age: self.last_new_file.elapsed(),
+        }
+    }
+
+    fn tick_info(&self) -> TickInfo {
+        let status_count = |matches: fn(&FileStatus<T, E>) -> bool| {
+            self.files_seen.values().filter(|f| matches(f)).count()
+        };
+
+        TickInfo {
+            tick: self.tick,
+            seen: self.files_seen.len() + self.yielded.len() + self.forgotten,
+            tracked: self.files_seen.len() + self.yielded.len(),
+            pending: status_count(|f| matches!(f, FileStatus::Processing(_))),
+            completed: self.stop_context().completed,
+            errored: status_count(|f| matches!(f, FileStatus::Error(_)))
+                + self
+                    .yielded
+                    .values()
+                    .filter(|succeeded| !**succeeded)
+                    .count(),
+            elapsed: self.start_time.elapsed(),
+        }
+    }
+
+    /// Stops tracking a file that another watcher claimed, remembering its modification time so
+    /// that it isn't processed again once the other watcher renames it back
+    fn claim_lost(&mut self, file: &Path) {
+        if let Some(FileStatus::Processing(modified)) = self.files_seen.get(file) {
+            self.claimed_elsewhere.insert(file.to_path_buf(), *modified);
+            self.files_seen.remove(file);
+            self.discovered.remove(file);
+            #[cfg(feature = "tracing")]
+            self.spans.remove(file);
+        }
+    }
+
+    /// Stops tracking files that were deleted or moved away after being processed, dropping
+    /// their results
+    fn forget_consumed(&mut self) {
+        for file in std::mem::take(&mut self.consumed) {
+            let succeeded = match self.files_seen.remove(&file) {
+                Some(FileStatus::ProcessingCompleted(_)) => true,
+                Some(_) => false,
+                None => self.yielded.remove(&file).unwrap_or(false),
+            };
+            if succeeded {
+                self.forgotten += 1;
+            }
+
+            self.hashes.remove(&file);
+            self.attempts.remove(&file);
+            self.discovered.remove(&file);
+            self.sequence.remove(&file);
+            self.retry_at.remove(&file);
+            self.unreadable.remove(&file);
+            self.observed.remove(&file);
+            self.processed_mtimes.remove(&file);
+            self.file_ids.remove(&file);
+        }
+    }
+
+    /// Opens the span for a newly tracked file
+    #[cfg(feature = "tracing")]
+    fn open_span(&mut self, file: &Path, size: u64) {
+        let span = tracing::info_span!(
+            "watch_file",
+            path = %file.display(),
+            size,
+            attempts = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        );
+        span.in_scope(|| tracing::debug!("seen"));
+        self.spans.insert(file.to_path_buf(), span);
+    }
+
+    /// The span for `file`, or a disabled span if it isn't tracked
+    #[cfg(feature = "tracing")]
+    fn span(&self, file: &Path) -> tracing::Span {
+        self.spans
+            .get(file)
+            .cloned()
+            .unwrap_or_else(tracing::Span::none)
+    }
+
+    /// Removes a file whose result is final, for [Watcher::watch_iter] to yield
+    fn take_finished(&mut self) -> Option<(PathBuf, Result<T, E>)> {
+        let file = self
+            .files_seen
+            .iter()
+            .find(|(_, status)| {
+                matches!(
+                    status,
+                    FileStatus::ProcessingCompleted(_) | FileStatus::Error(_)
+                )
+            })
+            .map(|(file, _)| file.clone())?;
+
+        let result = match self.files_seen.remove(&file)? {
+            FileStatus::ProcessingCompleted(t) => Ok(t),
+            FileStatus::Error(e) => Err(e),
+            _ => unreachable!(),
+        };
+        self.yielded.insert(file.clone(), result.is_ok());
+        Some((file, result))
+    }
+
+    /// Whether `file` isn't waiting out a [retry backoff](Watcher::retry)
+    fn retry_due(&self, file: &Path) -> bool {
+        self.retry_at
+            .get(file)
+            .is_none_or(|retry_at| *retry_at <= Instant::now())
+    }
+
+    /// Remembers that a file with `digest` was processed, forgetting the oldest digest once more
+    /// than `capacity` are remembered
+    fn remember_digest(&mut self, digest: String, file: &Path, capacity: usize) {
+        if self
+            .recent_digests
+            .insert(digest.clone(), file.to_path_buf())
+            .is_none()
+        {
+            self.digest_order.push_back(digest);
+        }
+
+        while self.digest_order.len() > capacity {
+            if let Some(oldest) = self.digest_order.pop_front() {
+                self.recent_digests.remove(&oldest);
+            }
+        }
+    }
+
+    fn into_results(self) -> FileResults<T, E> {
+        let mut completed = HashMap::new();
+        let mut not_processed = Vec::new();
+        let mut errored = HashMap::new();
+        let mut skipped = HashMap::new();
+        let mut awaiting_ack = HashMap::new();
+        let mut would_process = Vec::new();
+
+        let mut unreadable = self.unreadable;
+        for (path, status) in self.files_seen {
+            let still_failing = unreadable.remove(&path);
+            match status {
+                FileStatus::ProcessingCompleted(t) => {
+                    completed.insert(path, t);
+                }
+                FileStatus::Processing(_) => match still_failing {
+                    Some(e) => {
+                        errored.insert(path, e);
+                    }
+                    None => not_processed.push(path),
+                },
+                FileStatus::Error(e) => {
+                    errored.insert(path, e);
+                }
+                FileStatus::Skipped(reason) => {
+                    skipped.insert(path, reason);
+                }
+                FileStatus::AwaitingAck(t, _) => {
+                    awaiting_ack.insert(path, t);
+                }
+                FileStatus::WouldProcess => would_process.push(path),
+            }
+        }
+
+        // Files that were never readable, so were never tracked
+        errored.extend(unreadable);
+        would_process.sort_by_key(|path| self.sequence.get(path));
+
+        FileResults {
+            completed,
+            not_processed,
+            would_process,
+            errored,
+            skipped,
+            hashes: self.hashes,
+            combined_hash: self
+                .combined_hash
+                .map(|sum| sum.iter().map(|b| format!("{b:02x}")).collect()),
+            awaiting_ack,
+            deleted: self.deleted,
+            cleanup_failures: self.cleanup_failures,
+            matched_any: self.matched_any,
+            total_records: self.total_records,
+            forgotten: self.forgotten,
+            attempts: self.attempts,
+            sequence: self.sequence,
+            scan_time: self.scan_time,
+            process_time: self.process_time,
+        }
+    }
+}
+
+impl<F: Callback> Watcher<F> {
+    /// Only retries files whose error satisfies `predicate`, such as a timeout but not a parse
+    /// error. Other errors are recorded in [FileResults::errored] immediately. Retries are still
+    /// limited by [max_attempts](Self::max_attempts).
+    pub fn retry_if<P>(self, predicate: P) -> Watcher<RetryIf<F, P>>
+    where
+        P: Fn(&F::Error) -> bool,
+    {
+        self.map_callback(|inner| RetryIf { inner, predicate })
+    }
+
+    /// Sets a hook that is invoked as soon as the callback returns an error for a file, such as to
+    /// send an alert or record a metric, rather than waiting to inspect
+    /// [FileResults::errored] once watching stops. The hook receives the path that was passed to
+    /// the callback. It isn't invoked for failures that are recorded in [FileResults::skipped]
+    /// instead, such as a file that a [file callback](Self::with_file_callback) couldn't open.
+    pub fn on_error<H>(self, hook: H) -> Watcher<OnError<F, H>>
+    where
+        H: Fn(&Path, &F::Error),
+    {
+        self.map_callback(|inner| OnError {
+            inner,
+            hook,
+            skipped: None,
+        })
+    }
+
+    /// Delivers each file's result to `sink` as soon as it's final, rather than only in the
+    /// [FileResults] returned once watching stops. Successes are delivered when the callback
+    /// returns, and errors once the file won't be [retried](Self::max_attempts).
+    pub fn result_sink(
+        self,
+        sink: Box<dyn ResultSink<F::Output, F::Error> + Send + Sync>,
+    ) -> Watcher<WithSink<F>> {
+        self.map_callback(|inner| WithSink { inner, sink })
+    }
+
+    /// Drops each file's output as soon as the callback returns if `discard` is set, so that
+    /// [FileResults::completed] only records which files succeeded, mapping each to `None`. This
+    /// is for callbacks whose output is only needed to confirm success, and which would otherwise
+    /// hold every file's output in memory until watching stops. Otherwise, outputs are kept as
+    /// `Some`.
+    ///
+    /// Records are still counted toward [StopCondition::RecordsFound], and hooks applied before
+    /// this one (eg, a [result_sink](Self::result_sink)) still receive the output, as soon as the
+    /// callback returns.
+    pub fn discard_results(self, discard: bool) -> Watcher<Discard<F>> {
+        self.map_callback(|inner| Discard {
+            inner,
+            discard,
+            records: 0,
+        })
+    }
+
+    /// Gives up on the callback for a file once it has run for `limit`, recording a
+    /// [TimedOut](std::io::ErrorKind::TimedOut) error for the file rather than letting a hung
+    /// callback (eg, a network read with no timeout of its own) stall watching indefinitely. The
+    /// file is retried like any other error, up to [max_attempts](Self::max_attempts).
+    ///
+    /// Each call runs on a helper thread, so the callback must be `Send` and own what it uses. A
+    /// call that times out can't be killed, only abandoned: it keeps running in the background,
+    /// and the next file waits for it to finish (timing out in turn if it doesn't in time).
+    pub fn per_file_timeout(self, limit: Duration) -> Watcher<Timeout<F>>
+    where
+        F: Send + 'static,
+        F::Output: Send + 'static,
+        F::Error: From<std::io::Error> + Send + 'static,
+    {
+        self.map_callback(|inner| Timeout {
+            inner: std::sync::Arc::new(std::sync::Mutex::new(inner)),
+            limit,
+        })
+    }
+}
+
+impl<F> Watcher<F>
+where
+    F: Callback,
+    F::Error: From<std::io::Error>,
+{
+    pub fn watch(&mut self, condition: StopCondition) -> FileResults<F::Output, F::Error> {
+        let mut session = self.start();
+        while !self.step(&mut session, &condition) {}
+        session.into_results()
+    }
+
+    /// Same as [watch](Self::watch), but first checks that the watcher's source is usable,
+    /// returning an error rather than matching nothing if a glob pattern is invalid. The base
+    /// directories given to [base_dirs](Self::base_dirs), or the directory searched by
+    /// `new_regex`, must also be readable, whereas [watch](Self::watch) treats them as empty
+    /// until they can be read.
+    pub fn try_watch(
+        &mut self,
+        condition: StopCondition,
+    ) -> Result<FileResults<F::Output, F::Error>, WatchError> {
+        self.source.check()?;
+        Ok(self.watch(condition))
+    }
+
+    /// Watches until `condition` is met like [watch](Self::watch), but yields each file's result
+    /// as soon as it's final rather than collecting them all until watching stops. Each item is a
+    /// file that was processed successfully, or that failed and won't be
+    /// [retried](Self::retry).
+    ///
+    /// Only processed files are yielded; files that were skipped or hadn't matured when watching
+    /// stopped aren't reported.
+    pub fn watch_iter(&mut self, condition: StopCondition) -> WatchIter<'_, F> {
+        let session = self.start();
+        WatchIter {
+            watcher: self,
+            session,
+            condition,
+            done: false,
+        }
+    }
+}
+
+impl<F> Watcher<F>
+where
+    F: Handler,
+    F::Error: From<std::io::Error>,
+{
+    /// Creates the state for a new run of this watcher
+    fn start(&self) -> Session<F::Output, F::Error> {
+        if self.verbose {
+            log_info!("Starting watcher: {}", self.describe());
+        }
+
+        let memory_limit = self.ram_fraction.and_then(|fraction| {
+            let limit = available_memory().map(|available| (available as f64 * fraction) as u64);
+            if limit.is_none() && self.verbose {
+                log_warn!("Couldn't determine available memory; files won't be limited by size.");
+            }
+            limit
+        });
+
+        #[allow(unused_mut)]
+        let mut session = Session::new(memory_limit);
+
+        if self.only_new {
+            session.preexisting = self
+                .source
+                .paths(self.match_options)
+                .filter_map(|file| {
+                    let (modified, _) = modification_time_and_size(&file).ok()?;
+                    Some((file, modified))
+                })
+                .collect();
+        }
+
+        #[cfg(feature = "notify")]
+        if self.use_notify {
+            match crate::events::Events::new(&self.source.roots()) {
+                Ok(events) => session.events = Some(events),
+                Err(e) => {
+                    if self.verbose {
+                        log_warn!(
+                            "Couldn't watch for changes; checking for new files instead: {e}"
+                        );
+                    }
+                }
+            }
+        }
+
+        session
+    }
+
+    /// Runs a single check for files, first waiting until at least
+    /// [check_interval](Self::check_duration) has passed since the previous check. Returns
+    /// `true` once watching has finished.
+    fn step(
+        &mut self,
+        session: &mut Session<F::Output, F::Error>,
+        condition: &StopCondition,
+    ) -> bool {
+        if let Some(pause) = self.until_next_check(session) {
+            self.pause(pause);
+        }
+
+        self.check(session);
+        if !self.finish_check(session, condition) {
+            return false;
+        }
+
+        if let Some(deadline) = self.finalize_deadline(session) {
+            while let Some(pause) = self.finalize_pause(session, deadline) {
+                std::thread::sleep(pause);
+                self.scan(session);
+            }
+        }
+        self.release_held(session);
+        true
+    }
+
+    /// How long to wait before the next check so that at least
+    /// [check_interval](Self::check_duration) passes between checks, if at all
+    fn until_next_check(&self, session: &Session<F::Output, F::Error>) -> Option<Duration> {
+        let elapsed = session.last_check?.elapsed();
+        (self.check_interval > elapsed).then(|| self.check_interval - elapsed)
+    }
+
+    /// Reports on the check that just ran, returning whether watching should stop
+    fn finish_check(
+        &mut self,
+        session: &mut Session<F::Output, F::Error>,
+        condition: &StopCondition,
+    ) -> bool {
+        self.check_warnings(session);
+        self.report_tick(session);
+        self.should_stop(condition, session)
+    }
+
+    /// Runs a single check for files without waiting, including tidying up after files processed
+    /// by previous checks
+    fn check(&mut self, session: &mut Session<F::Output, F::Error>) {
+        session.last_check = Some(Instant::now());
+        // Files consumed during the previous check have been handed out by now, if iterating
+        session.forget_consumed();
+        self.sweep_retained(session);
+        if self.require_ack {
+            self.check_acks(session);
+        }
+        self.scan(session);
+    }
+
+    /// Checks all files matching the source, processing those that have matured once there are at
+    /// least [min_batch](Self::min_batch) of them
+    fn scan(&mut self, session: &mut Session<F::Output, F::Error>) {
+        let scan_start = Instant::now();
+        session.tick += 1;
+
+        if let Some(ignore_file) = &mut self.ignore_file {
+            ignore_file.refresh();
+        }
+
+        let mut matured = Vec::new();
+
+        // Time spent processing files as they're found, which isn't counted as scanning
+        let mut streamed = Duration::ZERO;
+
+        // Files waiting to mature, and how many new files were turned away for exceeding that limit
+        let mut tracked = session
+            .files_seen
+            .values()
+            .filter(|status| matches!(status, FileStatus::Processing(_)))
+            .count();
+        let mut refused = 0;
+
+        #[allow(unused_mut)]
+        let mut rescan = match (self.rescan_interval, session.last_rescan) {
+            (Some(interval), Some(last_rescan)) => last_rescan.elapsed() >= interval,
+            _ => true,
+        };
+
+        // Without a rescan interval, only scan again once something has changed
+        #[cfg(feature = "notify")]
+        if let (Some(events), Some(_)) = (&session.events, session.last_rescan) {
+            let changed = events.changed();
+            rescan = changed || (rescan && self.rescan_interval.is_some());
+        }
+
+        let files = if rescan {
+            session.last_rescan = Some(Instant::now());
+            self.source.paths(self.match_options)
+        } else {
+            // Between rescans, only re-check files we already know are waiting to mature
+            let tracked: Vec<_> = session
+                .files_seen
+                .iter()
+                .filter(|(_, status)| matches!(status, FileStatus::Processing(_)))
+                .map(|(file, _)| file.clone())
+                .collect();
+            Box::new(tracked.into_iter())
+        };
+
+        // Files whose metadata still couldn't be read when listed again by this rescan
+        let mut still_unreadable = HashSet::new();
+
+        for file in files {
+            if rescan {
+                session.matched_any = true;
+            }
+
+            if session.yielded.contains_key(&file) && !self.reprocess_on_change {
+                continue;
+            }
+
+            if let Some(ignore_file) = &self.ignore_file {
+                let status = session.files_seen.get(&file);
+                if ignore_file.is_ignored(&file) {
+                    // A file that's already been processed keeps its result
+                    if file != ignore_file.path()
+                        && matches!(status, None | Some(FileStatus::Processing(_)))
+                    {
+                        if self.verbose {
+                            log_info!("Skipping {}: it's ignored.", file.display());
+                        }
+                        self.emit(WatchEvent::Skipped(file.clone()));
+                        session.discovered.remove(&file);
+                        session.observed.remove(&file);
+                        #[cfg(feature = "tracing")]
+                        session.spans.remove(&file);
+                        session
+                            .files_seen
+                            .insert(file, FileStatus::Skipped(SkipReason::Ignored));
+                    }
+                    continue;
+                }
+
+                // The ignore file changed, so the file is tracked again as though it were new
+                if matches!(status, Some(FileStatus::Skipped(SkipReason::Ignored))) {
+                    session.files_seen.remove(&file);
+                }
+            }
+
+            if self
+                .excludes
+                .iter()
+                .any(|pattern| pattern.matches_path_with(&file, self.match_options))
+                || self.filter.as_ref().is_some_and(|filter| !filter(&file))
+                || self.has_ignored_suffix(&file)
+            {
+                continue;
+            }
+
+            if self.claim && is_claimed(&file) {
+                // Another watcher is processing this file under its private name
+                if let Some(original) = unclaimed_path(&file) {
+                    session.claim_lost(&original);
+                }
+                continue;
+            }
+
+            match modification_time_and_size(&file) {
+                Err(e) if !rescan && e.kind() == ErrorKind::NotFound => {
+                    // A tracked file disappeared; leave it as-is, same as if a rescan didn't find it
+                }
+                Err(e) => {
+                    // Couldn't get metadata->modified time, so we can't track it.
+                    let policy = self
+                        .metadata_error_policy
+                        .as_ref()
+                        .map_or(ErrorPolicy::Retry, |policy| policy(&e));
+
+                    if self.verbose {
+                        log_warn!(
+                            "Couldn't get metadata for {} ({policy:?}): {e:?}",
+                            file.display()
+                        );
+                    }
+
+                    if policy == ErrorPolicy::Retry {
+                        if rescan {
+                            still_unreadable.insert(file.clone());
+                        }
+                        session.unreadable.insert(file, e.into());
+                        continue;
+                    }
+
+                    let e = e.into();
+                    if !matches!(session.files_seen.get(&file), Some(FileStatus::Error(_))) {
+                        self.finished(&file, Err(&e));
+                    }
+                    #[cfg(feature = "tracing")]
+                    if let Some(span) = session.spans.remove(&file) {
+                        span.in_scope(|| tracing::warn!("couldn't read metadata"));
+                    }
+                    session.files_seen.insert(file, FileStatus::Error(e));
+                    if policy == ErrorPolicy::Abort {
+                        session.aborted = true;
+                        break;
+                    }
+                }
+                Ok((current_systime, size)) => {
+                    session.unreadable.remove(&file);
+
+                    if let Some(modified) = session.preexisting.get(&file) {
+                        if *modified == current_systime {
+                            continue;
+                        }
+                        session.preexisting.remove(&file);
+                    }
+
+                    if let Some(modified) = session.claimed_elsewhere.get(&file) {
+                        if *modified == current_systime {
+                            continue;
+                        }
+                        session.claimed_elsewhere.remove(&file);
+                    }
+
+                    if self.track_by_inode && !self.check_file_id(&file, current_systime, session) {
+                        continue;
+                    }
+
+                    if self.reprocess_on_change
+                        && session
+                            .processed_mtimes
+                            .get(&file)
+                            .is_some_and(|processed| *processed != current_systime)
+                    {
+                        if self.verbose {
+                            log_info!("{} changed; processing it again.", file.display());
+                        }
+                        session.processed_mtimes.remove(&file);
+                        session.yielded.remove(&file);
+                        session
+                            .files_seen
+                            .insert(file.clone(), FileStatus::Processing(current_systime));
+                    }
+
+                    if session.yielded.contains_key(&file) {
+                        continue;
+                    }
+
+                    if !session.files_seen.contains_key(&file) {
+                        if self.max_tracked_files.is_some_and(|limit| tracked >= limit) {
+                            refused += 1;
+                            continue;
+                        }
+                        tracked += 1;
+                        session.last_new_file = Instant::now();
+                        session
+                            .discovered
+                            .insert(file.clone(), session.next_discovery);
+                        session.next_discovery += 1;
+                        self.emit(WatchEvent::Seen(file.clone()));
+                        #[cfg(feature = "tracing")]
+                        session.open_span(&file, size);
+                    }
+
+                    let entry = session
+                        .files_seen
+                        .entry(file.clone())
+                        .or_insert_with(|| FileStatus::Processing(current_systime));
+
+                    if let FileStatus::Processing(last_seen) = entry {
+                        // The file was previously seen; update its last seen time (which may or may not be
+                        // different than what was previously set).
+                        if let Some(on_reset) = &self.on_reset {
+                            let shrank = session
+                                .observed
+                                .get(&file)
+                                .is_some_and(|observation| size < observation.size);
+                            if shrank || current_systime < *last_seen {
+                                on_reset(&file, current_systime);
+                            }
+                        }
+                        *last_seen = current_systime;
+
+                        if self.stable_size.is_some()
+                            || self.min_size.is_some()
+                            || self.max_wait.is_some()
+                            || self.clamp_future_mtimes
+                            || self.on_reset.is_some()
+                        {
+                            session
+                                .observed
+                                .entry(file.clone())
+                                .or_insert_with(|| Observation::new(current_systime, size))
+                                .update(current_systime, size);
+                        }
+
+                        // This file hasn't yet been processed
+                        if self.is_mature(&file, *last_seen, &session.observed)
+                            && session.retry_due(&file)
+                        {
+                            matured.push(file);
+                        }
+                    }
+
+                    if self.streaming && !matured.is_empty() {
+                        let process_start = Instant::now();
+                        self.process_all(std::mem::take(&mut matured), session);
+                        streamed += process_start.elapsed();
+                    }
+                }
+            }
+        }
+
+        // A file that's no longer listed has disappeared, so its stale error isn't reported
+        if rescan && !session.aborted {
+            session
+                .unreadable
+                .retain(|file, _| still_unreadable.contains(file));
+        }
+
+        session.scan_time += scan_start.elapsed().saturating_sub(streamed);
+
+        // Warn once each time the limit is reached, rather than at every check while it is
+        if refused > 0 {
+            if !session.refusing {
+                log_warn!(
+                    "Warning: {refused} new files weren't tracked because {tracked} files are \
+                     already waiting to mature."
+                );
+            }
+            session.refusing = true;
+        } else if self.max_tracked_files.is_none_or(|limit| tracked < limit) {
+            session.refusing = false;
+        }
+
+        if matured.len() >= self.min_batch && !session.aborted {
+            self.process_all(matured, session);
+        }
+    }
+
+    /// Sleeps for `duration`, waking early if the watcher is cancelled
+    fn pause(&self, duration: Duration) {
+        let Some(cancel) = &self.cancel else {
+            std::thread::sleep(duration);
+            return;
+        };
+
+        let deadline = Instant::now() + duration;
+        while !cancel.is_stopped() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            std::thread::sleep(remaining.min(CANCEL_POLL_INTERVAL));
+        }
+    }
+
+    /// Once watching stops, until when to keep checking for files that are waiting to mature, per
+    /// [finalize_wait](Self::finalize_wait)
+    fn finalize_deadline(&self, session: &Session<F::Output, F::Error>) -> Option<Instant> {
+        let wait = self.finalize_wait.filter(|_| !session.aborted)?;
+        if self.verbose {
+            log_info!("Waiting up to {wait:?} for remaining files to mature.");
+        }
+
+        Some(Instant::now() + wait)
+    }
+
+    /// How long to wait before checking files again while finalizing until `deadline`, or `None`
+    /// once it has passed or no files are waiting to mature
+    fn finalize_pause(
+        &self,
+        session: &Session<F::Output, F::Error>,
+        deadline: Instant,
+    ) -> Option<Duration> {
+        let pending = session
+            .files_seen
+            .values()
+            .any(|status| matches!(status, FileStatus::Processing(_)));
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !pending || remaining.is_zero() {
+            return None;
+        }
+
+        Some(self.check_interval.min(remaining))
+    }
+
+    /// Processes every tracked file that has matured, regardless of [min_batch](Self::min_batch).
+    /// This is done once watching stops so that a partial batch isn't left unprocessed.
+    fn release_held(&mut self, session: &mut Session<F::Output, F::Error>) {
+        if self.min_batch <= 1 || session.aborted {
+            return;
+        }
+
+        let matured = session
+            .files_seen
+            .iter()
+            .filter_map(|(file, status)| match status {
+                FileStatus::Processing(last_seen)
+                    if self.is_mature(file, *last_seen, &session.observed)
+                        && session.retry_due(file) =>
+                {
+                    Some(file.clone())
+                }
+                _ => None,
+            })
+            .collect();
+
+        self.process_all(matured, session);
+    }
+
+    /// Whether a file last modified at `last_seen` is ready to be processed
+    fn is_mature(
+        &self,
+        file: &Path,
+        last_seen: SystemTime,
+        observed: &HashMap<PathBuf, Observation>,
+    ) -> bool {
+        #[cfg(feature = "chrono")]
+        if let Some((start, end)) = self.blackout {
+            if in_window(last_seen, start, end) {
+                return false;
+            }
+        }
+
+        let waited_too_long = self.max_wait.is_some_and(|wait| {
+            observed
+                .get(file)
+                .is_some_and(|observation| observation.since.elapsed() >= wait)
+        });
+
+        let has_min_size = match self.min_size {
+            Some(min_size) => observed
+                .get(file)
+                .is_some_and(|observation| observation.size >= min_size),
+            None => true,
+        };
+
+        if !(waited_too_long || self.is_idle(file, last_seen, observed)) || !has_min_size {
+            return false;
+        }
+
+        // These read the file or look for its neighbors, so are only done once nothing else is
+        // holding the file back
+        self.required_trailer
+            .as_ref()
+            .is_none_or(|trailer| ends_with(file, trailer))
+            && self
+                .size_sidecar
+                .as_ref()
+                .is_none_or(|suffix| matches_sidecar(file, suffix))
+            && (!self.wait_for_partials
+                || !self
+                    .ignored_suffixes
+                    .iter()
+                    .any(|suffix| with_suffix(file, suffix).exists()))
+            && (!self.exclusive_access || can_lock(file))
+    }
+
+    /// Whether `file`'s name ends with one of the [ignored suffixes](Self::ignore_extensions)
+    fn has_ignored_suffix(&self, file: &Path) -> bool {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        self.ignored_suffixes
+            .iter()
+            .any(|suffix| name.ends_with(suffix.as_str()))
+    }
+
+    /// Whether a file last modified at `last_seen` has stopped changing for long enough, according
+    /// to its [maturation](Self::maturation) period and [stable size](Self::mature_on_stable_size)
+    fn is_idle(
+        &self,
+        file: &Path,
+        last_seen: SystemTime,
+        observed: &HashMap<PathBuf, Observation>,
+    ) -> bool {
+        let d = match last_seen.elapsed() {
+            Ok(d) => d,
+            // The modification time is in the future
+            Err(_) if self.clamp_future_mtimes => match observed.get(file) {
+                Some(observation) => observation.modified_since.elapsed(),
+                None => return false,
+            },
+            // If the Duration can't be calculated from the SystemTime, the file isn't mature yet
+            Err(_) => return false,
+        };
+
+        let has_stable_size = match self.stable_size {
+            Some(wait) => observed
+                .get(file)
+                .is_some_and(|observation| observation.size_since.elapsed() >= wait),
+            None => true,
+        };
+
+        d >= self.mature_after && has_stable_size
+    }
+
+    fn process_all(&mut self, matured: Vec<PathBuf>, session: &mut Session<F::Output, F::Error>) {
+        let matured = self.order_matured(matured, session);
+        let matured = match &self.dependencies {
+            Some(dependencies) => self.order_by_dependencies(matured, dependencies, session),
+            None => matured.into_iter().map(|file| (file, Vec::new())).collect(),
+        };
+
+        for (file, dependencies) in matured {
+            let waiting = dependencies.iter().any(|dependency| {
+                !matches!(
+                    session.files_seen.get(dependency),
+                    Some(FileStatus::ProcessingCompleted(_))
+                ) && session.yielded.get(dependency) != Some(&true)
+            });
+            if waiting {
+                if self.verbose {
+                    log_info!("{} is waiting on its dependencies.", file.display());
+                }
+                continue;
+            }
+
+            session.observed.remove(&file);
+            self.emit(WatchEvent::Mature(file.clone()));
+            if self.dry_run {
+                if self.verbose {
+                    log_info!("Would process {}.", file.display());
+                }
+                self.record(file, Some(FileStatus::WouldProcess), session);
+                continue;
+            }
+            #[cfg(feature = "tracing")]
+            session.span(&file).in_scope(|| tracing::debug!("matured"));
+            #[cfg(feature = "tokio")]
+            if let Some(deferred) = &mut session.deferred {
+                deferred.push(file);
+                continue;
+            }
+
+            let status = self.process(&file, session);
+            self.record(file, status, session);
+
+            if let Some(yield_between_files) = &mut self.yield_between_files {
+                yield_between_files();
+            }
+        }
+    }
+
+    /// Records the new status of a file that was just processed, where `None` means another
+    /// watcher claimed it first
+    fn record(
+        &mut self,
+        file: PathBuf,
+        status: Option<FileStatus<F::Output, F::Error>>,
+        session: &mut Session<F::Output, F::Error>,
+    ) {
+        match status {
+            Some(status) => {
+                match &status {
+                    FileStatus::ProcessingCompleted(t) | FileStatus::AwaitingAck(t, _) => {
+                        self.finished(&file, Ok(t))
+                    }
+                    FileStatus::Error(e) => self.finished(&file, Err(e)),
+                    FileStatus::Skipped(_) => self.emit(WatchEvent::Skipped(file.clone())),
+                    FileStatus::Processing(_) | FileStatus::WouldProcess => {}
+                }
+
+                if !matches!(status, FileStatus::Processing(_)) {
+                    session.sequence.insert(file.clone(), session.next_sequence);
+                    session.next_sequence += 1;
+                }
+
+                #[cfg(feature = "tracing")]
+                if !matches!(status, FileStatus::Processing(_)) {
+                    if let Some(span) = session.spans.remove(&file) {
+                        span.in_scope(|| match &status {
+                            FileStatus::Error(_) => tracing::warn!("failed"),
+                            FileStatus::Skipped(_) => tracing::info!("skipped"),
+                            FileStatus::WouldProcess => tracing::info!("would be processed"),
+                            _ => tracing::info!("processed"),
+                        });
+                    }
+                }
+
+                if let (true, FileStatus::ProcessingCompleted(_)) = (self.track_by_inode, &status) {
+                    // Files that were deleted or moved away needn't be remembered
+                    if let Some(id) = file_id(&file) {
+                        session.processed_ids.insert(id, file.clone());
+                    }
+                }
+
+                if let (Some(recent), FileStatus::ProcessingCompleted(_)) =
+                    (&mut session.recent, &status)
+                {
+                    recent.push(file.clone());
+                }
+
+                // A file put back to be retried hasn't been processed yet
+                if let (true, Some(FileStatus::Processing(modified))) =
+                    (self.reprocess_on_change, session.files_seen.get(&file))
+                {
+                    if !matches!(
+                        status,
+                        FileStatus::Processing(_) | FileStatus::AwaitingAck(..)
+                    ) {
+                        session.processed_mtimes.insert(file.clone(), *modified);
+                    }
+                }
+
+                session.files_seen.insert(file, status);
+            }
+            None => session.claim_lost(&file),
+        }
+    }
+
+    /// Sorts files that matured during the same check according to [order](Self::order)
+    fn order_matured(
+        &self,
+        mut matured: Vec<PathBuf>,
+        session: &Session<F::Output, F::Error>,
+    ) -> Vec<PathBuf> {
+        if matured.len() < 2 {
+            return matured;
+        }
+
+        let discovered = |file: &PathBuf| session.discovered.get(file).copied();
+        let modified = |file: &PathBuf| match session.files_seen.get(file) {
+            Some(FileStatus::Processing(modified)) => Some(*modified),
+            _ => None,
+        };
+        let size = |file: &PathBuf| file.metadata().map_or(0, |metadata| metadata.len());
+
+        match self.order {
+            ProcessOrder::Fifo => matured.sort_by_key(discovered),
+            ProcessOrder::Lifo => matured.sort_by_key(|file| std::cmp::Reverse(discovered(file))),
+            ProcessOrder::OldestModifiedFirst => matured.sort_by_key(modified),
+            ProcessOrder::NewestModifiedFirst => {
+                matured.sort_by_key(|file| std::cmp::Reverse(modified(file)))
+            }
+            ProcessOrder::SmallestFirst => matured.sort_by_cached_key(size),
+            ProcessOrder::LargestFirst => {
+                matured.sort_by_cached_key(|file| std::cmp::Reverse(size(file)))
+            }
+        }
+
+        matured
+    }
+
+    /// Orders `matured` so that each file comes after any matured files it depends on, pairing
+    /// each with its dependencies. Files involved in a cycle are recorded as errored and omitted.
+    fn order_by_dependencies(
+        &self,
+        matured: Vec<PathBuf>,
+        dependencies: &DependencyFn,
+        session: &mut Session<F::Output, F::Error>,
+    ) -> Vec<(PathBuf, Vec<PathBuf>)> {
+        let in_batch: HashSet<PathBuf> = matured.iter().cloned().collect();
+        let mut remaining: Vec<_> = matured
+            .into_iter()
+            .map(|file| {
+                let depends_on = dependencies(&file);
+                (file, depends_on)
+            })
+            .collect();
+
+        // Repeatedly take every file whose dependencies within this batch have all been taken
+        let mut ordered = Vec::with_capacity(remaining.len());
+        let mut taken = HashSet::new();
+        loop {
+            let (ready, blocked): (Vec<_>, Vec<_>) =
+                remaining.into_iter().partition(|(_, depends_on)| {
+                    depends_on.iter().all(|dependency| {
+                        !in_batch.contains(dependency) || taken.contains(dependency)
+                    })
+                });
+            remaining = blocked;
+
+            if ready.is_empty() {
+                break;
+            }
+            taken.extend(ready.iter().map(|(file, _)| file.clone()));
+            ordered.extend(ready);
+        }
+
+        for (file, _) in remaining {
+            if self.verbose {
+                log_error!("{} is in or depends on a dependency cycle.", file.display());
+            }
+            let e = std::io::Error::other(format!(
+                "{} is part of or depends on a dependency cycle",
+                file.display()
+            ));
+            let e = e.into();
+            self.finished(&file, Err(&e));
+            #[cfg(feature = "tracing")]
+            if let Some(span) = session.spans.remove(&file) {
+                span.in_scope(|| tracing::warn!("dependency cycle"));
+            }
+            session.files_seen.insert(file, FileStatus::Error(e));
+        }
+
+        ordered
+    }
+
+    /// Records the inode of `file`, returning whether it should continue to be tracked. A file that
+    /// was processed under another path is skipped, while a processed file whose inode has changed
+    /// is reset so that it's processed again. See [track_by_inode](Self::track_by_inode).
+    fn check_file_id(
+        &self,
+        file: &Path,
+        modified: SystemTime,
+        session: &mut Session<F::Output, F::Error>,
+    ) -> bool {
+        let Some(id) = file_id(file) else {
+            return true;
+        };
+
+        let previous = session.file_ids.insert(file.to_path_buf(), id);
+        match session.files_seen.get(file) {
+            None => match session.processed_ids.get(&id) {
+                Some(from) if from != file => {
+                    if self.verbose {
+                        log_info!(
+                            "{} was already processed as {}.",
+                            file.display(),
+                            from.display()
+                        );
+                    }
+                    self.emit(WatchEvent::Skipped(file.to_path_buf()));
+                    let reason = SkipReason::Renamed { from: from.clone() };
+                    session
+                        .files_seen
+                        .insert(file.to_path_buf(), FileStatus::Skipped(reason));
+                    false
+                }
+                _ => true,
+            },
+            Some(FileStatus::Processing(_)) => true,
+            Some(_) if previous.is_some_and(|previous| previous != id) => {
+                if self.verbose {
+                    log_info!("{} was replaced; processing it again.", file.display());
+                }
+                session
+                    .files_seen
+                    .insert(file.to_path_buf(), FileStatus::Processing(modified));
+                true
+            }
+            Some(_) => true,
+        }
+    }
+
+    /// Passes the session's progress to [on_tick](Self::on_tick), if set
+    fn report_tick(&mut self, session: &Session<F::Output, F::Error>) {
+        if let Some(on_tick) = &mut self.on_tick {
+            on_tick(&session.tick_info());
+        }
+    }
+
+    /// Prints each soft-limit warning the first time its limit is crossed
+    fn check_warnings(&self, session: &mut Session<F::Output, F::Error>) {
+        if self.warn_after_files.is_none() && self.warn_after_duration.is_none() {
+            return;
+        }
+
+        let ctx = session.stop_context();
+
+        if let (Some(limit), false) = (self.warn_after_files, session.warned_files) {
+            if ctx.completed > limit {
+                log_warn!(
+                    "Warning: {} files have been processed, more than the expected {limit}.",
+                    ctx.completed
+                );
+                session.warned_files = true;
+            }
+        }
+
+        if let (Some(limit), false) = (self.warn_after_duration, session.warned_duration) {
+            if ctx.elapsed > limit {
+                log_warn!("Warning: the watcher has been running for more than {limit:?}.");
+                session.warned_duration = true;
+            }
+        }
+    }
+
+    /// Whether watching should stop, either because `condition` has been met or because the
+    /// watcher was cancelled
+    fn should_stop(
+        &mut self,
+        condition: &StopCondition,
+        session: &Session<F::Output, F::Error>,
+    ) -> bool {
+        if session.aborted {
+            if self.verbose {
+                log_info!("Processing halted: a file's metadata couldn't be read.");
+            }
+            return true;
+        }
+
+        if condition.is_satisfied(&session.stop_context()) {
+            if self.verbose {
+                match condition {
+                    StopCondition::Once => {}
+                    StopCondition::FilesFound(n) => {
+                        log_info!("Processing halted: {n} files have been successfully processed.")
+                    }
+                    StopCondition::RecordsFound(n) => {
+                        log_info!("Processing halted: {n} records have been produced.")
+                    }
+                    StopCondition::Elapsed(d) => {
+                        log_info!("Processing halted: {d:?} elapsed since processing started.")
+                    }
+                    StopCondition::NoNewFilesSince(d) => {
+                        log_info!(
+                            "Processing halted: {d:?} elapsed since a new file has been seen."
+                        )
+                    }
+                    StopCondition::Any(_) | StopCondition::All(_) => {
+                        log_info!("Processing halted: the combined stop condition was met.")
+                    }
+                }
+            }
+
+            return true;
+        }
+
+        if self.cancel.as_ref().is_some_and(StopHandle::is_stopped) {
+            if self.verbose {
+                log_info!("Processing halted: watcher was cancelled.");
+            }
+            return true;
+        }
+
+        if let Some(stop_when) = &mut self.stop_when {
+            if stop_when(&session.stop_context()) {
+                if self.verbose {
+                    log_info!("Processing halted: the stop_when predicate was satisfied.");
+                }
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Runs the callback on a matured file, returning its new status, or `None` if another
+    /// watcher claimed the file first.
+    fn process(
+        &mut self,
+        file: &Path,
+        session: &mut Session<F::Output, F::Error>,
+    ) -> Option<FileStatus<F::Output, F::Error>> {
+        // Entered for the rest of processing, so that the callback's own events are correlated
+        #[cfg(feature = "tracing")]
+        let span = session.span(file);
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+
+        let prepared = match self.prepare(file, session) {
+            Ok(prepared) => prepared,
+            Err(status) => return status,
+        };
+
+        let process_start = Instant::now();
+        let result = if self.catch_panics {
+            let call = std::panic::AssertUnwindSafe(|| self.callback.call_event(&prepared.event));
+            std::panic::catch_unwind(call).unwrap_or_else(|payload| Err(panic_error(payload)))
+        } else {
+            self.callback.call_event(&prepared.event)
+        };
+
+        Some(self.complete(file, prepared, result, process_start.elapsed(), session))
+    }
+
+    /// Checks whether a matured file should be passed to the callback, claiming it if
+    /// [claim](Self::claim) is set. Otherwise, returns the file's new status, or `None` if another
+    /// watcher claimed the file first.
+    fn prepare(
+        &mut self,
+        file: &Path,
+        session: &mut Session<F::Output, F::Error>,
+    ) -> Result<Prepared, Option<Status<F>>> {
+        let metadata = match file.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => return Err(Some(FileStatus::Error(e.into()))),
+        };
+
+        if let Some(limit) = session.memory_limit {
+            if metadata.len() > limit {
+                if self.verbose {
+                    log_warn!(
+                        "Skipping {}: {} bytes exceeds the memory limit of {limit} bytes.",
+                        file.display(),
+                        metadata.len()
+                    );
+                }
+                return Err(Some(FileStatus::Skipped(SkipReason::TooLargeForMemory {
+                    size: metadata.len(),
+                    limit,
+                })));
+            }
+        }
+
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            Err(e) => return Err(Some(FileStatus::Error(e.into()))),
+        };
+
+        let digest = match self.dedup_capacity {
+            Some(_) => match DEDUP_ALGO.digest_file(file) {
+                Ok(digest) => {
+                    if let Some(original) = session.recent_digests.get(&digest) {
+                        if self.verbose {
+                            log_info!(
+                                "Skipping {}: same contents as {}.",
+                                file.display(),
+                                original.display()
+                            );
+                        }
+                        return Err(Some(FileStatus::Skipped(SkipReason::Duplicate {
+                            of: original.clone(),
+                        })));
+                    }
+                    Some(digest)
+                }
+                Err(e) => return Err(Some(FileStatus::Error(e.into()))),
+            },
+            None => None,
+        };
+
+        let target = if self.claim {
+            let claimed = claimed_path(file, session.claimant);
+            match std::fs::rename(file, &claimed) {
+                Ok(()) => claimed,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    if self.verbose {
+                        log_info!("{} was claimed by another watcher.", file.display());
+                    }
+                    return Err(None);
+                }
+                Err(e) => return Err(Some(FileStatus::Error(e.into()))),
+            }
+        } else {
+            file.to_path_buf()
+        };
+
+        let event = FileEvent {
+            path: target.clone(),
+            tick: session.tick,
+            modified,
+            size: metadata.len(),
+        };
+        Ok(Prepared {
+            target,
+            event,
+            digest,
+        })
+    }
+
+    /// Records the callback's `result` for a prepared file, which took `elapsed`, and disposes of
+    /// the file if it succeeded. Returns the file's new status.
+    fn complete(
+        &mut self,
+        file: &Path,
+        prepared: Prepared,
+        result: Result<F::Output, F::Error>,
+        elapsed: Duration,
+        session: &mut Session<F::Output, F::Error>,
+    ) -> FileStatus<F::Output, F::Error> {
+        let Prepared { target, digest, .. } = prepared;
+
+        let attempts = session.attempts.entry(file.to_path_buf()).or_insert(0);
+        *attempts += 1;
+        let attempts = *attempts;
+        session.process_time += elapsed;
+
+        #[cfg(feature = "tracing")]
+        {
+            let span = session.span(file);
+            span.record("attempts", attempts);
+            span.record("elapsed_ms", elapsed.as_millis() as u64);
+        }
+
+        if let Ok(output) = &result {
+            session.total_records += self.callback.records(output);
+        }
+
+        if let (Ok(_), Some(digest), Some(capacity)) = (&result, digest, self.dedup_capacity) {
+            session.remember_digest(digest, file, capacity);
+        }
+
+        if let (Ok(_), Some(algo)) = (&result, self.hash_algo) {
+            match algo.digest_file(&target) {
+                Ok(digest) => {
+                    session.hashes.insert(file.to_path_buf(), digest);
+                }
+                Err(e) if self.verbose => {
+                    log_error!("Couldn't hash {}: {e:?}", file.display());
+                }
+                Err(_) => {}
+            }
+        }
+
+        if let (Ok(_), Some(algo)) = (&result, self.combined_algo) {
+            let digest = match session.hashes.get(file) {
+                Some(digest) if self.hash_algo == Some(algo) => Ok(digest.clone()),
+                _ => algo.digest_file(&target),
+            };
+
+            match digest {
+                Ok(digest) => {
+                    add_digest(session.combined_hash.get_or_insert_with(Vec::new), &digest)
+                }
+                Err(e) if self.verbose => {
+                    log_error!("Couldn't hash {}: {e:?}", file.display());
+                }
+                Err(_) => {}
+            }
+        }
+
+        if result.is_ok() && !self.require_ack {
+            self.dispose(file, &target, session);
+        } else if self.claim {
+            self.release_claim(&target, file);
+        }
+
+        if result.is_err() {
+            if let Some(reason) = self.callback.skip_reason() {
+                if self.verbose {
+                    log_warn!("Skipping {}: {reason:?}.", file.display());
+                }
+                return FileStatus::Skipped(reason);
+            }
+        }
+
+        match result {
+            Ok(t) if self.require_ack => match modification_time(file) {
+                Ok(modified) => FileStatus::AwaitingAck(t, modified),
+                // Already gone, so the consumer has acknowledged it
+                Err(_) => FileStatus::ProcessingCompleted(t),
+            },
+            Ok(t) => FileStatus::ProcessingCompleted(t),
+            Err(e) if attempts < self.max_attempts && self.callback.should_retry(&e) => {
+                if self.verbose {
+                    log_info!(
+                        "Attempt {attempts} of {} failed for {}; retrying.",
+                        self.max_attempts,
+                        file.display()
+                    );
+                }
+                match session.files_seen.get(file) {
+                    Some(FileStatus::Processing(last_seen)) => {
+                        let retry_at = Instant::now() + self.retry_backoff;
+                        session.retry_at.insert(file.to_path_buf(), retry_at);
+                        FileStatus::Processing(*last_seen)
+                    }
+                    _ => FileStatus::Error(e),
+                }
+            }
+            Err(e) => FileStatus::Error(e),
+        }
+    }
+
+    /// Reports a file's final result to the callback and writes its marker, if any
+    fn finished(&self, file: &Path, result: Result<&F::Output, &F::Error>) {
+        self.callback.finished(file, result);
+        self.emit(match result {
+            Ok(_) => WatchEvent::Completed(file.to_path_buf()),
+            Err(_) => WatchEvent::Errored(file.to_path_buf()),
+        });
+
+        let (marker, stale) = match result {
+            Ok(_) => (&self.completion_marker, &self.failure_marker),
+            Err(_) => (&self.failure_marker, &self.completion_marker),
+        };
+
+        if let Some(suffix) = stale {
+            let stale = with_suffix(file, suffix);
+            if let (Err(e), true) = (std::fs::remove_file(&stale), stale.exists()) {
+                if self.verbose {
+                    log_error!("Couldn't remove marker {}: {e:?}", stale.display());
+                }
+            }
+        }
+
+        if let Some(suffix) = marker {
+            let marker = with_suffix(file, suffix);
+            if let (Err(e), true) = (std::fs::File::create(&marker), self.verbose) {
+                log_error!("Couldn't write marker {}: {e:?}", marker.display());
+            }
+        }
+    }
+
+    /// Completes processed files that have since been deleted, and requeues those that were
+    /// modified instead. See [require_ack](Self::require_ack).
+    fn check_acks(&self, session: &mut Session<F::Output, F::Error>) {
+        let awaiting: Vec<_> = session
+            .files_seen
+            .iter()
+            .filter(|(_, status)| matches!(status, FileStatus::AwaitingAck(..)))
+            .map(|(file, _)| file.clone())
+            .collect();
+
+        for file in awaiting {
+            let Some(FileStatus::AwaitingAck(t, processed_at)) = session.files_seen.remove(&file)
+            else {
+                continue;
+            };
+
+            let status = match modification_time(&file) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    if self.verbose {
+                        log_info!("{} was acknowledged.", file.display());
+                    }
+                    if let Some(recent) = &mut session.recent {
+                        recent.push(file.clone());
+                    }
+                    FileStatus::ProcessingCompleted(t)
+                }
+                Ok(modified) if modified != processed_at => {
+                    if self.verbose {
+                        log_info!(
+                            "{} was modified before being acknowledged; requeuing it.",
+                            file.display()
+                        );
+                    }
+                    FileStatus::Processing(modified)
+                }
+                _ => FileStatus::AwaitingAck(t, processed_at),
+            };
+
+            session.files_seen.insert(file, status);
+        }
+    }
+
+    /// Archives, deletes, or leaves in place a successfully processed file, as configured.
+    /// `target` is where the file currently is, which differs from `file` if it was claimed.
+    fn dispose(&self, file: &Path, target: &Path, session: &mut Session<F::Output, F::Error>) {
+        let delete = self.should_delete(file);
+
+        let remaining = if let Some(dir) = &self.archive_dir {
+            let moved = std::fs::create_dir_all(dir).and_then(|()| {
+                let archived = archive_path(dir, file);
+                std::fs::rename(target, &archived).map(|()| archived)
+            });
+            match moved {
+                Ok(archived) => {
+                    if self.verbose {
+                        log_info!(
+                            "Processed and moved {} to {}.",
+                            file.display(),
+                            archived.display()
+                        );
+                    }
+                    self.record_consumed(file, None, session);
+                    Some(archived)
+                }
+                Err(e) => {
+                    if self.verbose {
+                        log_error!("Processed but failed to move {}: {e:?}", file.display());
+                    }
+                    Some(target.to_path_buf())
+                }
+            }
+        } else if delete && self.delete_after.is_none() {
+            // Companions must be found before the file itself is deleted
+            let companions = self.callback.companions(target);
+
+            match std::fs::remove_file(target) {
+                Ok(_) => {
+                    if self.verbose {
+                        log_info!("Processed and deleted {}.", file.display());
+                    }
+                    if self.confirm_deleted(file, session) {
+                        self.emit(WatchEvent::Deleted(file.to_path_buf()));
+                        self.record_consumed(file, Some(file), session);
+                    }
+                }
+                Err(e) => {
+                    if self.verbose {
+                        log_error!("Processed but failed to delete {}: {e:?}", file.display());
+                    }
+                    session.cleanup_failures.insert(file.to_path_buf(), e);
+                }
+            }
+
+            for companion in companions {
+                match std::fs::remove_file(&companion) {
+                    Ok(_) => {
+                        self.emit(WatchEvent::Deleted(companion.clone()));
+                        if !self.forget_consumed {
+                            session.deleted.push(companion);
+                        }
+                    }
+                    Err(e) => {
+                        if self.verbose {
+                            log_error!(
+                                "Processed but failed to delete {}: {e:?}",
+                                companion.display()
+                            );
+                        }
+                        session.cleanup_failures.insert(companion, e);
+                    }
+                }
+            }
+
+            None
+        } else {
+            Some(target.to_path_buf())
+        };
+
+        // A claimed file that wasn't moved elsewhere goes back to its original name
+        let remaining = remaining.map(|path| {
+            if self.claim && path == target {
+                self.release_claim(target, file);
+                file.to_path_buf()
+            } else {
+                path
+            }
+        });
+
+        if let (Some(path), Some(_), true) = (remaining, self.delete_after, delete) {
+            session.retained.push_back((path, Instant::now()));
+        }
+    }
+
+    /// Whether a successfully processed file should eventually be deleted, according to the
+    /// first matching [delete rule](Self::delete_rules) or else the global settings
+    fn should_delete(&self, file: &Path) -> bool {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        self.delete_rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&name))
+            .map_or(
+                self.delete_on_completion || self.delete_after.is_some(),
+                |(_, delete)| *delete,
+            )
+    }
+
+    /// Whether a just-deleted `path` is actually gone, recording a cleanup failure if it isn't.
+    /// Always `true` unless [verify_consumed](Self::verify_consumed) is set.
+    fn confirm_deleted(&self, path: &Path, session: &mut Session<F::Output, F::Error>) -> bool {
+        if !self.verify_consumed || std::fs::symlink_metadata(path).is_err() {
+            return true;
+        }
+
+        if self.verbose {
+            log_warn!("{} still exists after being deleted.", path.display());
+        }
+        let e = std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            "file still exists after being deleted",
+        );
+        session.cleanup_failures.insert(path.to_path_buf(), e);
+        false
+    }
+
+    /// Notes that processed `file` no longer exists under its name, and that `deleted` was
+    /// deleted, either to be [forgotten](Self::forget_consumed) or to be reported in
+    /// [FileResults::deleted]
+    fn record_consumed(
+        &self,
+        file: &Path,
+        deleted: Option<&Path>,
+        session: &mut Session<F::Output, F::Error>,
+    ) {
+        if self.forget_consumed {
+            session.consumed.push(file.to_path_buf());
+        } else if let Some(deleted) = deleted {
+            session.deleted.push(deleted.to_path_buf());
+        }
+    }
+
+    fn release_claim(&self, target: &Path, file: &Path) {
+        if let (Err(e), true) = (std::fs::rename(target, file), self.verbose) {
+            log_error!("Couldn't release claim on {}: {e:?}", file.display());
+        }
+    }
+
+    /// Deletes processed files whose [retention period](Self::delete_after) has elapsed
+    fn sweep_retained(&self, session: &mut Session<F::Output, F::Error>) {
+        let Some(retention) = self.delete_after else {
+            return;
+        };
+
+        while let Some((_, since)) = session.retained.front() {
+            if since.elapsed() < retention {
+                break;
+            }
+
+            let (path, _) = session.retained.pop_front().unwrap();
+
+            match std::fs::remove_file(&path) {
+                Ok(_) => {
+                    if self.verbose {
+                        log_info!("Deleted {} after its retention period.", path.display());
+                    }
+                    if self.confirm_deleted(&path, session) {
+                        self.emit(WatchEvent::Deleted(path.clone()));
+                        self.record_consumed(&path, Some(&path), session);
+                    }
+                }
+                Err(e) => {
+                    if self.verbose {
+                        log_error!("Failed to delete {}: {e:?}", path.display());
+                    }
+                    session.cleanup_failures.insert(path, e);
+                }
+            }
+        }
+    }
+}
+
+/// Compiles the patterns of [Watcher::delete_rules], panicking if any is invalid
+fn compile_rules(rules: Vec<(String, bool)>) -> Vec<(glob::Pattern, bool)> {
+    rules
+        .into_iter()
+        .map(|(pattern, delete)| {
+            let pattern = glob::Pattern::new(&pattern).expect("Invalid delete rule pattern");
+            (pattern, delete)
+        })
+        .collect()
+}
+
+/// The algorithm used to detect duplicate files for [Watcher::dedup_lru]
+const DEDUP_ALGO: ChecksumAlgo = ChecksumAlgo::Fnv1a64;
+
+/// How often a cancellable watcher checks its [StopHandle] while waiting between checks
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The prefix given to files that have been claimed by a watcher
+const CLAIM_PREFIX: &str = ".claimed-";
+
+/// The number identifying the next watch run in this process to claim files
+static NEXT_CLAIMANT: AtomicU64 = AtomicU64::new(0);
+
+/// The private name a file is renamed to when it is claimed by `claimant` in this process
+fn claimed_path(path: &Path, claimant: u64) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(
+        "{CLAIM_PREFIX}{}-{claimant}-{name}",
+        std::process::id()
+    ))
+}
+
+/// The original path of a file that a watcher claimed, if `path` is a claimed file's name
+fn unclaimed_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?.strip_prefix(CLAIM_PREFIX)?;
+    let (_pid, name) = name.split_once('-')?;
+    let (_claimant, name) = name.split_once('-')?;
+    Some(path.with_file_name(name))
+}
+
+fn is_claimed(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(CLAIM_PREFIX))
+}
+
+/// Checks whether the file at `path` ends with `trailer`, reading only as many bytes as needed
+fn ends_with(path: &Path, trailer: &[u8]) -> bool {
+    let read_tail = || -> Result<bool, std::io::Error> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() < trailer.len() as u64 {
+            return Ok(false);
+        }
+
+        file.seek(SeekFrom::End(-(trailer.len() as i64)))?;
+        let mut tail = vec![0; trailer.len()];
+        file.read_exact(&mut tail)?;
+        Ok(tail == trailer)
+    };
+
+    read_tail().unwrap_or(false)
+}
+
+/// `path` with `suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    path.into()
+}
+
+/// The error recorded for a callback that panicked with `payload`, if [Watcher::catch_panics] was
+/// set
+fn panic_error<E: From<std::io::Error>>(payload: Box<dyn std::any::Any + Send>) -> E {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    std::io::Error::other(format!("callback panicked: {message}")).into()
+}
+
+/// Where `file` is moved to in the archive directory `dir`: its own name if that's free, or else
+/// with the first unused number inserted before its extension
+fn archive_path(dir: &Path, file: &Path) -> PathBuf {
+    let archived = dir.join(file.file_name().unwrap_or_default());
+    if std::fs::symlink_metadata(&archived).is_err() {
+        return archived;
+    }
+
+    (1..)
+        .map(|n| {
+            let mut name = file.file_stem().unwrap_or_default().to_os_string();
+            name.push(format!(".{n}"));
+            if let Some(extension) = file.extension() {
+                name.push(".");
+                name.push(extension);
+            }
+            dir.join(name)
+        })
+        .find(|candidate| std::fs::symlink_metadata(candidate).is_err())
+        .expect("Ran out of archive names")
+}
+
+/// Whether the file at `path` can be locked exclusively with `flock`, meaning no other process is
+/// holding a lock on it. The lock is released when the file is closed, as soon as it's taken.
+#[cfg(unix)]
+fn can_lock(path: &Path) -> bool {
+    use std::os::raw::c_int;
+    use std::os::unix::io::AsRawFd;
+
+    const LOCK_EX: c_int = 2;
+    const LOCK_NB: c_int = 4;
+
+    extern "C" {
+        fn flock(fd: c_int, operation: c_int) -> c_int;
+    }
+
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+
+    // SAFETY: the descriptor belongs to `file`, which is open for the duration of the call
+    unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) == 0 }
+}
+
+/// Whether the whole file at `path` can be locked exclusively with `LockFileEx`, meaning no other
+/// process is holding a lock on any part of it. The lock is released as soon as it's taken.
+#[cfg(windows)]
+fn can_lock(path: &Path) -> bool {
+    use std::os::windows::io::{AsRawHandle, RawHandle};
+
+    const LOCKFILE_FAIL_IMMEDIATELY: u32 = 0x1;
+    const LOCKFILE_EXCLUSIVE_LOCK: u32 = 0x2;
+
+    /// `OVERLAPPED`, which gives the offset of the locked range
+    #[repr(C)]
+    struct Overlapped {
+        internal: usize,
+        internal_high: usize,
+        offset: u32,
+        offset_high: u32,
+        event: RawHandle,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LockFileEx(
+            file: RawHandle,
+            flags: u32,
+            reserved: u32,
+            bytes_low: u32,
+            bytes_high: u32,
+            overlapped: *mut Overlapped,
+        ) -> i32;
+        fn UnlockFile(
+            file: RawHandle,
+            offset_low: u32,
+            offset_high: u32,
+            bytes_low: u32,
+            bytes_high: u32,
+        ) -> i32;
+    }
+
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+
+    let handle = file.as_raw_handle();
+    let mut overlapped = Overlapped {
+        internal: 0,
+        internal_high: 0,
+        offset: 0,
+        offset_high: 0,
+        event: std::ptr::null_mut(),
+    };
+    let flags = LOCKFILE_FAIL_IMMEDIATELY | LOCKFILE_EXCLUSIVE_LOCK;
+
+    // SAFETY: the handle belongs to `file`, which is open for the duration of both calls, and
+    // `overlapped` outlives the first, which returns immediately since the handle isn't
+    // overlapped
+    unsafe {
+        if LockFileEx(handle, flags, 0, u32::MAX, u32::MAX, &mut overlapped) == 0 {
+            return false;
+        }
+        // Closing the file releases the lock too, but not necessarily right away
+        UnlockFile(handle, 0, 0, u32::MAX, u32::MAX);
+    }
+    true
+}
+
+#[cfg(not(any(unix, windows)))]
+fn can_lock(_path: &Path) -> bool {
+    true
+}
+
+/// Checks whether the size of the file at `path` equals the size recorded in its sidecar file,
+/// which is `path` with `suffix` appended
+fn matches_sidecar(path: &Path, suffix: &str) -> bool {
+    let sidecar = with_suffix(path, suffix);
+
+    let read_size = || -> Option<bool> {
+        let expected: u64 = std::fs::read_to_string(sidecar).ok()?.trim().parse().ok()?;
+        Some(path.metadata().ok()?.len() == expected)
+    };
+
+    read_size().unwrap_or(false)
+}
+
+/// Whether `time` falls within the daily window from `start` (inclusive) to `end` (exclusive),
+/// in local time
+#[cfg(feature = "chrono")]
+fn in_window(time: SystemTime, start: chrono::NaiveTime, end: chrono::NaiveTime) -> bool {
+    let time = chrono::DateTime::<chrono::Local>::from(time).time();
+    if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    }
+}
+
+/// Identifies a file independently of its path, as its device and inode numbers
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = path.metadata().ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<FileId> {
+    None
+}
+
+/// Result flattening [is unstable](https://github.com/rust-lang/rust/issues/70142),
+/// so this function simplifies getting the system time from a file
+fn modification_time(path: &Path) -> Result<SystemTime, std::io::Error> {
+    let metadata = path.metadata()?;
+    let modified = metadata.modified()?;
+    Ok(modified)
+}
+
+fn modification_time_and_size(path: &Path) -> Result<(SystemTime, u64), std::io::Error> {
+    let metadata = path.metadata()?;
+    let modified = metadata.modified()?;
+    Ok((modified, metadata.len()))
+}
+
+/// The number of bytes of memory currently available, if it can be determined
+fn available_memory() -> Option<u64> {
+    if cfg!(target_os = "linux") {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+        let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kb * 1024)
+    } else {
+        None
+    }
 }
@@ -0,0 +1,57 @@
+#![cfg(feature = "notify")]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use watch_files::{StopCondition, Watcher};
+
+/// Watches a fresh directory in which one file exists beforehand, another is created while
+/// watching and a third doesn't match, returning each processed file's contents by name
+fn watch(name: &str, use_notify: bool) -> (HashMap<String, String>, Vec<PathBuf>) {
+    let dir = std::env::temp_dir().join(format!("watch-files-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("before.txt"), "before").unwrap();
+
+    let writer = {
+        let dir = dir.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(150));
+            std::fs::write(dir.join("during.txt"), "during").unwrap();
+            std::fs::write(dir.join("ignored.csv"), "ignored").unwrap();
+        })
+    };
+
+    let results = Watcher::new(dir.join("*.txt").to_string_lossy(), |path: &Path| {
+        std::fs::read_to_string(path)
+    })
+    .maturation(Duration::from_millis(100))
+    .check_duration(Duration::from_millis(20))
+    .use_notify(use_notify)
+    .watch(StopCondition::Elapsed(Duration::from_millis(800)));
+    writer.join().unwrap();
+
+    assert!(results.errored.is_empty());
+    let completed = results
+        .completed
+        .into_iter()
+        .map(|(path, contents)| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, contents)
+        })
+        .collect();
+    (completed, results.not_processed)
+}
+
+#[test]
+fn notify_finds_the_same_files_as_polling() {
+    let (polled, polled_pending) = watch("notify-polled", false);
+    let (notified, notified_pending) = watch("notify-notified", true);
+
+    assert_eq!(polled.len(), 2);
+    assert_eq!(polled.get("during.txt").map(String::as_str), Some("during"));
+    assert_eq!(notified, polled);
+    assert!(polled_pending.is_empty());
+    assert!(notified_pending.is_empty());
+}
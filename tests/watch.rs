@@ -0,0 +1,736 @@
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use watch_files::{ErrorPolicy, FileResults, SkipReason, StopCondition, Watcher, WatcherGroup};
+
+/// An empty directory for a single test, removed first if a previous run left it behind
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("watch-files-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn pattern(dir: &Path, glob: &str) -> String {
+    dir.join(glob).to_string_lossy().into_owned()
+}
+
+/// Sets `path`'s modification time to `ago` before now
+fn backdate(path: &Path, ago: Duration) {
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - ago)
+        .unwrap();
+}
+
+fn read(path: &Path) -> std::io::Result<String> {
+    std::fs::read_to_string(path)
+}
+
+/// Stops once `files` have been processed, or after a few seconds if something went wrong
+fn files_found(files: usize) -> StopCondition {
+    StopCondition::Any(vec![
+        StopCondition::FilesFound(files),
+        StopCondition::Elapsed(Duration::from_secs(5)),
+    ])
+}
+
+#[cfg(unix)]
+#[test]
+fn metadata_error_is_retried_until_readable() {
+    let dir = test_dir("metadata-retry");
+    let target = dir.join("target");
+    let link = dir.join("a.txt");
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let failures = Rc::new(Cell::new(0));
+    let counted = failures.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .check_duration(Duration::from_millis(20))
+        .metadata_error_policy(move |_| {
+            counted.set(counted.get() + 1);
+            ErrorPolicy::Retry
+        })
+        .on_tick(move |tick| {
+            if tick.tick == 1 {
+                std::fs::write(&target, "contents").unwrap();
+            }
+        })
+        .watch(files_found(1));
+
+    assert_eq!(failures.get(), 1);
+    assert_eq!(
+        results.completed.get(&link).map(String::as_str),
+        Some("contents")
+    );
+    assert!(results.errored.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn vanished_unreadable_file_isnt_reported() {
+    let dir = test_dir("metadata-vanished");
+    let link = dir.join("a.txt");
+    std::os::unix::fs::symlink(dir.join("target"), &link).unwrap();
+
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .check_duration(Duration::from_millis(20))
+        .on_tick(move |tick| {
+            if tick.tick == 1 {
+                std::fs::remove_file(&link).unwrap();
+            }
+        })
+        .watch(StopCondition::Elapsed(Duration::from_millis(200)));
+
+    assert!(results.errored.is_empty(), "{:?}", results.errored.keys());
+    assert_eq!(results.total(), 0);
+}
+
+#[test]
+fn claimed_file_is_processed_once_without_deletion() {
+    let dir = test_dir("claim");
+    std::fs::write(dir.join("a.txt"), "contents").unwrap();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut group = WatcherGroup::new();
+    for _ in 0..2 {
+        let pattern = pattern(&dir, "*.txt");
+        let calls = calls.clone();
+        group.spawn(move |handle| {
+            Watcher::new(pattern, move |path: &Path| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                // Long enough for the other watcher to see the file being claimed
+                std::thread::sleep(Duration::from_millis(100));
+                read(path)
+            })
+            .maturation(Duration::from_millis(50))
+            .check_duration(Duration::from_millis(10))
+            .claim(true)
+            .with_cancel(handle)
+            .watch(StopCondition::Elapsed(Duration::from_millis(500)))
+        });
+    }
+
+    let completed: usize = group
+        .join()
+        .iter()
+        .map(|results| {
+            results
+                .downcast_ref::<FileResults<String, std::io::Error>>()
+                .unwrap()
+                .completed
+                .len()
+        })
+        .sum();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(completed, 1);
+    assert!(dir.join("a.txt").exists());
+}
+
+#[test]
+fn elapsed_processes_files_created_before_it_elapses() {
+    let dir = test_dir("elapsed");
+    let file = dir.join("a.txt");
+
+    let created = Rc::new(Cell::new(false));
+    let create = created.clone();
+    let path = file.clone();
+    let start = std::time::Instant::now();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::from_millis(200))
+        .check_duration(Duration::from_millis(100))
+        .on_tick(move |tick| {
+            if tick.elapsed >= Duration::from_secs(2) && !create.get() {
+                std::fs::write(&path, "contents").unwrap();
+                create.set(true);
+            }
+        })
+        .watch(StopCondition::Elapsed(Duration::from_secs(5)));
+
+    assert!(created.get());
+    assert!(start.elapsed() >= Duration::from_secs(5));
+    assert_eq!(
+        results.completed.get(&file).map(String::as_str),
+        Some("contents")
+    );
+}
+
+#[test]
+fn reprocess_on_change_processes_modified_file_again() {
+    let dir = test_dir("reprocess");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "v1").unwrap();
+    // Far enough in the past that rewriting the file always changes its modification time
+    backdate(&file, Duration::from_secs(60));
+
+    let calls = Rc::new(Cell::new(0));
+    let counted = calls.clone();
+    let modified = Rc::new(Cell::new(false));
+    let modify = modified.clone();
+    let path = file.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), move |path: &Path| {
+        counted.set(counted.get() + 1);
+        read(path)
+    })
+    .maturation(Duration::ZERO)
+    .check_duration(Duration::from_millis(20))
+    .reprocess_on_change(true)
+    .on_tick(move |tick| {
+        if tick.completed == 1 && !modify.get() {
+            std::fs::write(&path, "v2").unwrap();
+            modify.set(true);
+        }
+    })
+    .watch(StopCondition::Elapsed(Duration::from_millis(500)));
+
+    assert!(modified.get());
+    assert_eq!(calls.get(), 2);
+    assert_eq!(results.completed.get(&file).map(String::as_str), Some("v2"));
+}
+
+#[test]
+fn no_new_files_since_counts_from_start_in_empty_directory() {
+    let dir = test_dir("no-new-files-empty");
+
+    let start = std::time::Instant::now();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .check_duration(Duration::from_millis(20))
+        .watch(StopCondition::NoNewFilesSince(Duration::from_millis(300)));
+
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    assert_eq!(results.total(), 0);
+}
+
+#[test]
+fn no_new_files_since_counts_preexisting_files_from_when_theyre_found() {
+    let dir = test_dir("no-new-files-preexisting");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "contents").unwrap();
+    // Modified long before the duration, which mustn't stop watching on the first check
+    backdate(&file, Duration::from_secs(3600));
+
+    let start = std::time::Instant::now();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .check_duration(Duration::from_millis(20))
+        .watch(StopCondition::NoNewFilesSince(Duration::from_millis(300)));
+
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert_eq!(
+        results.completed.get(&file).map(String::as_str),
+        Some("contents")
+    );
+}
+
+#[test]
+fn exclude_skips_matching_files() {
+    let dir = test_dir("exclude");
+    std::fs::write(dir.join("a.csv"), "a").unwrap();
+    std::fs::write(dir.join("b.tmp.csv"), "b").unwrap();
+
+    let results = Watcher::new(pattern(&dir, "*.csv"), read)
+        .exclude("*.tmp.csv")
+        .maturation(Duration::ZERO)
+        .check_duration(Duration::from_millis(20))
+        .watch(StopCondition::Elapsed(Duration::from_millis(200)));
+
+    assert_eq!(results.total(), 1);
+    assert!(results.completed.contains_key(&dir.join("a.csv")));
+}
+
+#[test]
+fn mature_on_stable_size_waits_for_growth_to_stop() {
+    use std::io::Write;
+
+    let dir = test_dir("stable-size");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "0").unwrap();
+    // Appends don't change the modification time, as on some network shares
+    let modified = std::time::SystemTime::now() - Duration::from_secs(3600);
+    let set_modified = move |path: &Path| {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(modified).unwrap();
+    };
+    set_modified(&file);
+
+    let path = file.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .mature_on_stable_size(Duration::from_millis(100))
+        .check_duration(Duration::from_millis(20))
+        .on_tick(move |tick| {
+            if tick.tick <= 5 {
+                let mut file = std::fs::File::options().append(true).open(&path).unwrap();
+                write!(file, "{}", tick.tick).unwrap();
+                set_modified(&path);
+            }
+        })
+        .watch(files_found(1));
+
+    assert_eq!(
+        results.completed.get(&file).map(String::as_str),
+        Some("012345")
+    );
+}
+
+#[test]
+fn catch_panics_records_panicking_file_as_errored() {
+    let dir = test_dir("catch-panics");
+    for name in ["a.txt", "bad.txt", "c.txt"] {
+        std::fs::write(dir.join(name), name).unwrap();
+    }
+
+    let results = Watcher::new(pattern(&dir, "*.txt"), |path: &Path| {
+        if path.ends_with("bad.txt") {
+            panic!("can't process {}", path.display());
+        }
+        read(path)
+    })
+    .maturation(Duration::ZERO)
+    .catch_panics(true)
+    .watch(StopCondition::Once);
+
+    assert_eq!(results.completed.len(), 2);
+    assert!(results.completed.contains_key(&dir.join("a.txt")));
+    assert!(results.completed.contains_key(&dir.join("c.txt")));
+    let error = &results.errored[&dir.join("bad.txt")];
+    assert!(error.to_string().contains("can't process"), "{error}");
+}
+
+#[test]
+fn min_size_waits_for_empty_file_to_be_filled() {
+    let dir = test_dir("min-size");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "").unwrap();
+
+    let path = file.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .min_size(1)
+        .check_duration(Duration::from_millis(20))
+        .on_tick(move |tick| {
+            if tick.tick == 3 {
+                std::fs::write(&path, "contents").unwrap();
+            }
+        })
+        .watch(files_found(1));
+
+    assert_eq!(
+        results.completed.get(&file).map(String::as_str),
+        Some("contents")
+    );
+}
+
+#[test]
+fn max_wait_processes_file_that_keeps_changing() {
+    let dir = test_dir("max-wait");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "contents").unwrap();
+
+    let path = file.clone();
+    let start = std::time::Instant::now();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::from_secs(2))
+        .max_wait(Duration::from_millis(200))
+        .check_duration(Duration::from_millis(20))
+        // Touched at every check, so it's never idle for the maturation period
+        .on_tick(move |_| backdate(&path, Duration::ZERO))
+        .watch(files_found(1));
+
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    assert_eq!(
+        results.completed.get(&file).map(String::as_str),
+        Some("contents")
+    );
+}
+
+#[test]
+fn forget_consumed_doesnt_keep_results_of_deleted_files() {
+    let dir = test_dir("forget-consumed");
+
+    let created = dir.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .check_duration(Duration::from_millis(10))
+        .delete_on_completion(true)
+        .forget_consumed(true)
+        .on_tick(move |tick| {
+            if tick.tick <= 10 {
+                let file = created.join(format!("{}.txt", tick.tick));
+                std::fs::write(file, "contents").unwrap();
+            }
+        })
+        // Files are forgotten at the check after they're processed, so keep checking after the last
+        .watch(StopCondition::Elapsed(Duration::from_millis(300)));
+
+    assert_eq!(results.forgotten, 10);
+    assert!(results.completed.is_empty());
+    assert!(results.deleted.is_empty());
+    assert!(results.attempts.is_empty());
+    assert!(results.sequence.is_empty());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn new_header_with_unbounded_len_reads_whole_file() {
+    let dir = test_dir("header");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "contents").unwrap();
+
+    let results = Watcher::new_header(pattern(&dir, "*.txt"), usize::MAX, |_, header| {
+        Ok::<_, std::io::Error>(header.to_vec())
+    })
+    .maturation(Duration::ZERO)
+    .watch(StopCondition::Once);
+
+    assert_eq!(results.completed[&file], b"contents");
+}
+
+#[cfg(unix)]
+#[test]
+fn on_error_isnt_invoked_for_files_that_couldnt_be_opened() {
+    use std::io::Read;
+
+    let dir = test_dir("on-error-skipped");
+    // A socket has metadata but can't be opened like a file
+    let _socket = std::os::unix::net::UnixListener::bind(dir.join("a.sock")).unwrap();
+    std::fs::write(dir.join("b.sock"), "bad").unwrap();
+
+    let reported = Rc::new(RefCell::new(Vec::new()));
+    let report = reported.clone();
+    let results = Watcher::with_file_callback(
+        pattern(&dir, "*.sock"),
+        |_, mut file: std::fs::File| -> std::io::Result<()> {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)?;
+            Err(std::io::Error::other(contents))
+        },
+    )
+    .maturation(Duration::ZERO)
+    .on_error(move |path, _| report.borrow_mut().push(path.to_path_buf()))
+    .watch(StopCondition::Once);
+
+    assert!(matches!(
+        results.skipped.get(&dir.join("a.sock")),
+        Some(SkipReason::OpenFailed { .. })
+    ));
+    assert!(results.errored.contains_key(&dir.join("b.sock")));
+    assert_eq!(*reported.borrow(), [dir.join("b.sock")]);
+}
+
+#[test]
+fn on_reset_ignores_appends_but_reports_truncation() {
+    use std::io::Write;
+
+    let dir = test_dir("on-reset");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "0").unwrap();
+
+    let resets = Rc::new(Cell::new(0));
+    let reset = resets.clone();
+    let path = file.clone();
+    Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::from_secs(10))
+        .check_duration(Duration::from_millis(20))
+        .on_reset(move |_, _| reset.set(reset.get() + 1))
+        .on_tick(move |tick| match tick.tick {
+            1..=3 => {
+                let mut file = std::fs::File::options().append(true).open(&path).unwrap();
+                write!(file, "{}", tick.tick).unwrap();
+            }
+            5 => std::fs::write(&path, "").unwrap(),
+            _ => {}
+        })
+        .watch(StopCondition::Elapsed(Duration::from_millis(300)));
+
+    assert_eq!(resets.get(), 1);
+}
+
+#[test]
+fn require_trailing_waits_for_trailer() {
+    use std::io::Write;
+
+    let dir = test_dir("trailer");
+    let file = dir.join("a.txt");
+    std::fs::write(&file, "line").unwrap();
+
+    let path = file.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .require_trailing(b"\n".to_vec())
+        .check_duration(Duration::from_millis(20))
+        .on_tick(move |tick| {
+            if tick.tick == 3 {
+                let mut file = std::fs::File::options().append(true).open(&path).unwrap();
+                writeln!(file).unwrap();
+            }
+        })
+        .watch(files_found(1));
+
+    assert_eq!(
+        results.completed.get(&file).map(String::as_str),
+        Some("line\n")
+    );
+}
+
+#[test]
+fn group_stops_other_watchers_when_one_panics() {
+    let dir = test_dir("group-panic");
+
+    let mut group = WatcherGroup::new();
+    let pattern = pattern(&dir, "*.txt");
+    group.spawn(move |handle| {
+        Watcher::new(pattern, read)
+            .with_cancel(handle)
+            .watch(StopCondition::Elapsed(Duration::MAX))
+    });
+    group.spawn(|_| -> FileResults<String, std::io::Error> { panic!("watcher failed") });
+
+    // Joined on another thread, so that a hang fails the test rather than stalling it
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let joined = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| group.join()));
+        sender.send(joined.is_err()).unwrap();
+    });
+    let panicked = receiver.recv_timeout(Duration::from_secs(5));
+    assert_eq!(panicked, Ok(true));
+}
+
+#[test]
+fn watchers_in_one_process_claim_files_under_different_names() {
+    let dir = test_dir("claim-names");
+    for i in 0..20 {
+        std::fs::write(dir.join(format!("{i}.txt")), "contents").unwrap();
+    }
+
+    let claimed = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut group = WatcherGroup::new();
+    for watcher in 0..2 {
+        let pattern = pattern(&dir, "*.txt");
+        let claimed = claimed.clone();
+        group.spawn(move |handle| {
+            Watcher::new(pattern, move |path: &Path| {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                claimed.lock().unwrap().push((watcher, name));
+                std::thread::sleep(Duration::from_millis(5));
+                read(path)
+            })
+            .maturation(Duration::ZERO)
+            .check_duration(Duration::from_millis(10))
+            .claim(true)
+            .delete_on_completion(true)
+            .with_cancel(handle)
+            .watch(StopCondition::Elapsed(Duration::from_millis(500)))
+        });
+    }
+    group.join();
+
+    let claimed = claimed.lock().unwrap();
+    assert_eq!(claimed.len(), 20);
+    for (watcher, name) in claimed.iter() {
+        // The claimed name, less the file's own name, identifies the watcher that claimed it
+        let (claimant, _) = name.rsplit_once('-').unwrap();
+        assert!(claimant.starts_with(".claimed-"), "{name}");
+        assert!(claimed
+            .iter()
+            .filter(|(other, _)| other != watcher)
+            .all(|(_, other)| !other.starts_with(claimant)));
+    }
+    // Nothing was left behind under a claimed name
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn once_processes_only_matured_files() {
+    let dir = test_dir("once");
+    let old = dir.join("old.txt");
+    let new = dir.join("new.txt");
+    std::fs::write(&old, "old").unwrap();
+    backdate(&old, Duration::from_secs(60));
+    std::fs::write(&new, "new").unwrap();
+
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::from_secs(10))
+        .watch(StopCondition::Once);
+
+    assert_eq!(results.completed.get(&old).map(String::as_str), Some("old"));
+    assert_eq!(results.not_processed, [new]);
+}
+
+#[test]
+fn ignore_file_records_ignored_files_as_skipped() {
+    let dir = test_dir("ignore-file");
+    std::fs::write(dir.join(".watchignore"), "b.txt\n").unwrap();
+    std::fs::write(dir.join("a.txt"), "a").unwrap();
+    std::fs::write(dir.join("b.txt"), "b").unwrap();
+
+    let results = Watcher::new(pattern(&dir, "*"), read)
+        .ignore_file(dir.join(".watchignore"))
+        .maturation(Duration::ZERO)
+        .watch(StopCondition::Once);
+
+    assert!(results.completed.contains_key(&dir.join("a.txt")));
+    assert!(matches!(
+        results.skipped.get(&dir.join("b.txt")),
+        Some(SkipReason::Ignored)
+    ));
+    // The ignore file itself isn't reported
+    assert_eq!(results.total(), 2);
+}
+
+#[test]
+fn forget_consumed_keeps_tracked_files_bounded() {
+    const FILES_PER_CHECK: usize = 50;
+    const CHECKS: usize = 20;
+
+    let dir = test_dir("forget-consumed-bounded");
+
+    let most_tracked = Rc::new(Cell::new(0));
+    let tracked = most_tracked.clone();
+    let created = dir.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), read)
+        .maturation(Duration::ZERO)
+        .check_duration(Duration::from_millis(10))
+        .delete_on_completion(true)
+        .forget_consumed(true)
+        .on_tick(move |tick| {
+            tracked.set(tracked.get().max(tick.tracked));
+            if tick.tick as usize <= CHECKS {
+                for i in 0..FILES_PER_CHECK {
+                    let file = created.join(format!("{}-{i}.txt", tick.tick));
+                    std::fs::write(file, "contents").unwrap();
+                }
+            }
+        })
+        // Keep checking after the last files are created, so that they're forgotten too
+        .watch(StopCondition::Elapsed(Duration::from_millis(800)));
+
+    assert_eq!(results.forgotten, FILES_PER_CHECK * CHECKS);
+    // Only the files processed by the latest check are still tracked at any time
+    assert!(
+        most_tracked.get() <= FILES_PER_CHECK,
+        "{}",
+        most_tracked.get()
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn watch_async_releases_partial_batch_when_stopping() {
+    let dir = test_dir("async-batch");
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        std::fs::write(dir.join(name), name).unwrap();
+    }
+
+    let mut watcher = Watcher::new_async(pattern(&dir, "*.txt"), |path: PathBuf| async move {
+        std::fs::read_to_string(path)
+    })
+    .maturation(Duration::ZERO)
+    .check_duration(Duration::from_millis(20))
+    .min_batch(5)
+    .concurrency(2);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let results =
+        runtime.block_on(watcher.watch_async(StopCondition::Elapsed(Duration::from_millis(200))));
+
+    assert_eq!(results.completed.len(), 3);
+    assert_eq!(
+        results
+            .completed
+            .get(&dir.join("b.txt"))
+            .map(String::as_str),
+        Some("b.txt")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn require_exclusive_access_waits_for_writer_to_unlock() {
+    use std::os::unix::io::AsRawFd;
+
+    extern "C" {
+        fn flock(fd: i32, operation: i32) -> i32;
+    }
+
+    let dir = test_dir("exclusive-access");
+    let path = dir.join("a.txt");
+    std::fs::write(&path, "contents").unwrap();
+
+    // Held like a writer would, but through a read-only handle like the watcher's own
+    let writer = RefCell::new(Some(std::fs::File::open(&path).unwrap()));
+    let fd = writer.borrow().as_ref().unwrap().as_raw_fd();
+    // SAFETY: the descriptor stays open until the writer is dropped; 2 | 4 is LOCK_EX | LOCK_NB
+    assert_eq!(unsafe { flock(fd, 2 | 4) }, 0);
+
+    let tick = Rc::new(Cell::new(0));
+    let current = tick.clone();
+    let processed_at = Rc::new(Cell::new(0));
+    let processed = processed_at.clone();
+    let results = Watcher::new(pattern(&dir, "*.txt"), move |path: &Path| {
+        processed.set(tick.get());
+        read(path)
+    })
+    .maturation(Duration::ZERO)
+    .check_duration(Duration::from_millis(20))
+    .require_exclusive_access(true)
+    .on_tick(move |info| {
+        current.set(info.tick);
+        if info.tick == 3 {
+            writer.borrow_mut().take();
+        }
+    })
+    .watch(files_found(1));
+
+    assert_eq!(results.completed.len(), 1);
+    // Processed by the first check after the writer unlocked it, following the third
+    assert_eq!(processed_at.get(), 3);
+}
+
+#[test]
+fn discard_results_drops_outputs_only_when_set() {
+    let dir = test_dir("discard-results");
+    for name in ["a.txt", "b.txt"] {
+        std::fs::write(dir.join(name), name).unwrap();
+    }
+
+    for discard in [true, false] {
+        let results = Watcher::new(pattern(&dir, "*.txt"), read)
+            .maturation(Duration::ZERO)
+            .discard_results(discard)
+            .watch(StopCondition::Once);
+
+        assert_eq!(results.completed.len(), 2);
+        assert_eq!(results.total_records, 2);
+        let kept = results.completed.get(&dir.join("a.txt")).unwrap();
+        assert_eq!(kept.as_deref(), (!discard).then_some("a.txt"));
+    }
+}
+
+#[test]
+fn invalid_expanded_pattern_matches_nothing_rather_than_panicking() {
+    let dir = test_dir("invalid-expanded");
+    std::fs::write(dir.join("a.txt"), "a").unwrap();
+
+    // Expands to `*.txt` and `***`, which isn't a valid glob
+    let mut watcher = Watcher::new(pattern(&dir, "{*.txt,***}"), read).maturation(Duration::ZERO);
+    let results = watcher.watch(StopCondition::Once);
+    assert_eq!(results.completed.len(), 1);
+
+    assert!(watcher.try_watch(StopCondition::Once).is_err());
+}